@@ -1,9 +1,15 @@
 use std::{
     ffi::OsStr,
     fs,
-    process::{Command, exit},
+    io::{BufRead, BufReader, Read, Write},
+    process::{Command, Stdio, exit},
 };
 
+/// Must match `FRAME_MAGIC` in the kernel's `screenshot` module
+const SCREENSHOT_FRAME_MAGIC: &str = "FAJR-SCREENSHOT";
+
+const SCREENSHOTS_DIR: &str = "screenshots";
+
 enum Arch {
     X86_64,
 }
@@ -53,6 +59,64 @@ where
         .unwrap();
 }
 
+/// Executes the command while forwarding its output, except for the screenshots the kernel
+/// streams over serial, which are saved into the screenshots directory instead
+fn exec_capturing_screenshots<C>(command: C)
+where
+    C: AsRef<str>,
+{
+    let mut command = command.as_ref().split_whitespace();
+
+    let program = command.next().unwrap();
+
+    let mut child = Command::new(program)
+        .args(command)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut output = BufReader::new(child.stdout.take().unwrap());
+    let mut stdout = std::io::stdout();
+    let mut line = Vec::new();
+    let mut screenshot_count = 0;
+
+    fs::create_dir_all(SCREENSHOTS_DIR).unwrap();
+
+    while output.read_until(b'\n', &mut line).unwrap() != 0 {
+        let text = String::from_utf8_lossy(&line);
+        let mut fields = text.trim_end().split(' ');
+
+        if fields.next() == Some(SCREENSHOT_FRAME_MAGIC) {
+            let (Some(format), Some(Ok(length))) =
+                (fields.next(), fields.nth(2).map(str::parse::<usize>))
+            else {
+                eprintln!("malformed screenshot header: {}", text.trim_end());
+                line.clear();
+                continue;
+            };
+
+            let mut image = vec![0; length];
+
+            output.read_exact(&mut image).unwrap();
+
+            let path = format!("{SCREENSHOTS_DIR}/screenshot-{screenshot_count}.{format}");
+
+            fs::write(&path, image).unwrap();
+
+            screenshot_count += 1;
+
+            eprintln!("saved a screenshot to {path}");
+        } else if text.trim() != format!("{SCREENSHOT_FRAME_MAGIC}-END") {
+            stdout.write_all(&line).unwrap();
+            stdout.flush().unwrap();
+        }
+
+        line.clear();
+    }
+
+    child.wait().unwrap();
+}
+
 pub fn main() {
     let mut args = std::env::args();
 
@@ -64,6 +128,8 @@ pub fn main() {
     let mut iso = true;
     let mut bios = true;
     let mut clippy = false;
+    let mut capture = false;
+    let mut features = Vec::new();
 
     while let Some(arg) = args.next() {
         match arg.as_str() {
@@ -110,6 +176,14 @@ pub fn main() {
                     "bios" => bios = true,
                     "uefi" => bios = false,
                     "clippy" => clippy = true,
+                    "capture" => capture = true,
+
+                    "feature" => {
+                        features.push(args.next().unwrap_or_else(|| {
+                            eprintln!("expected a kernel feature");
+                            exit(1);
+                        }));
+                    }
 
                     _ => {
                         eprintln!("unknown key: {key}");
@@ -137,10 +211,23 @@ pub fn main() {
         }
     };
 
+    let cargo_features = if features.is_empty() {
+        String::new()
+    } else {
+        format!(" --features {}", features.join(","))
+    };
+
+    // Allocation tracking finds where allocations come from by following frame pointers
+    let rust_flags = if features.iter().any(|feature| feature == "alloc_tracking") {
+        "-C relocation-model=static -C force-frame-pointers=yes"
+    } else {
+        "-C relocation-model=static"
+    };
+
     if clippy {
         exece(
-            format!("cargo clippy -p fajr_kernel --target {rust_target}"),
-            [("RUSTFLAGS", "-C relocation-model=static")].into_iter(),
+            format!("cargo clippy -p fajr_kernel --target {rust_target}{cargo_features}"),
+            [("RUSTFLAGS", rust_flags)].into_iter(),
         );
 
         return;
@@ -172,8 +259,10 @@ pub fn main() {
     let image_path = "fajr-".to_string() + arch.as_str() + if iso { ".iso" } else { ".hdd" };
 
     exece(
-        format!("cargo build -p fajr_kernel --target {rust_target} --profile {rust_profile}"),
-        [("RUSTFLAGS", "-C relocation-model=static")].into_iter(),
+        format!(
+            "cargo build -p fajr_kernel --target {rust_target} --profile {rust_profile}{cargo_features}"
+        ),
+        [("RUSTFLAGS", rust_flags)].into_iter(),
     );
 
     fs::copy(
@@ -252,22 +341,26 @@ pub fn main() {
     if !only_build {
         let qemu_program = "qemu-system-".to_string() + arch.as_str();
 
-        if bios {
+        let qemu_command = if bios {
             if iso {
-                exec(format!(
-                    "{qemu_program} -m 4G -M q35 -cdrom {image_path} -boot d -smp 2"
-                ));
+                format!(
+                    "{qemu_program} -m 4G -M q35 -cdrom {image_path} -boot d -smp 2 -serial stdio"
+                )
             } else {
-                exec(format!(
-                    "{qemu_program} -m 4G -M q35 -hda {image_path} -smp 2"
-                ));
+                format!("{qemu_program} -m 4G -M q35 -hda {image_path} -smp 2 -serial stdio")
             }
         } else {
-            exec(format!(
+            format!(
                 "{qemu_program} -M q35 -drive if=pflash,unit=0,format=raw,file=ovmf/{ovmf_code},readonly=on
-                -drive if=pflash,unit=1,format=raw,file=ovmf/{ovmf_vars} {} {image_path}",
+                -drive if=pflash,unit=1,format=raw,file=ovmf/{ovmf_vars} -serial stdio {} {image_path}",
                 if iso { "-cdrom" } else { "-hda" }
-            ));
+            )
+        };
+
+        if capture {
+            exec_capturing_screenshots(qemu_command);
+        } else {
+            exec(qemu_command);
         }
     }
 }
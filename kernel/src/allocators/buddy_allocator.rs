@@ -0,0 +1,322 @@
+use core::ptr::NonNull;
+
+use crate::paging::{self, MIN_PAGE_SIZE};
+
+/// Blocks can be up to 2^MAX_ORDER frames, which is 1 GiB
+pub const MAX_ORDER: usize = 18;
+
+/// A physical frame number, which is the physical address divided by the page size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct Frame(pub usize);
+
+impl Frame {
+    #[inline(always)]
+    pub const fn from_phys(phys: usize) -> Frame {
+        Frame(phys / MIN_PAGE_SIZE)
+    }
+
+    #[inline(always)]
+    pub const fn phys(&self) -> usize {
+        self.0 * MIN_PAGE_SIZE
+    }
+
+    /// Get the address of the frame in the higher half direct map
+    #[inline(always)]
+    pub fn virt(&self) -> usize {
+        paging::offset(self.phys())
+    }
+}
+
+/// The smallest order of a block that has at least `frame_count` frames
+#[inline(always)]
+pub fn get_order_of(frame_count: usize) -> usize {
+    frame_count.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+/// Our information about a single physical frame, only meaningful for the first frame of a
+/// block
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct FrameInfo {
+    pub order: u8,
+    pub is_free: bool,
+    /// Amount of owners of an allocated block, it is freed once the last one lets go of it
+    pub ref_count: u16,
+}
+
+impl FrameInfo {
+    pub const fn new() -> FrameInfo {
+        FrameInfo {
+            order: 0,
+            is_free: false,
+            ref_count: 0,
+        }
+    }
+}
+
+impl Default for FrameInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Lives in the first frame of every free block, through the higher half direct map
+struct FreeBlock {
+    previous: Option<NonNull<FreeBlock>>,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// Manages physical frames in blocks of power of two frames, a block of order `n` is always
+/// aligned to 2^n frames, so the buddy it was split from can be found by flipping a bit
+pub struct BuddyAllocator {
+    free_lists: [Option<NonNull<FreeBlock>>; MAX_ORDER + 1],
+    /// The first frame described by `frames`
+    base: Frame,
+    frames: &'static mut [FrameInfo],
+    free_frame_count: usize,
+    total_frame_count: usize,
+}
+
+unsafe impl Send for BuddyAllocator {}
+
+impl BuddyAllocator {
+    /// Makes an allocator without any free frames, which can only ever manage the frames
+    /// described by `frames`, starting at `base`, blocks are never merged past these frames
+    pub fn new(base: Frame, frames: &'static mut [FrameInfo]) -> BuddyAllocator {
+        frames.fill(FrameInfo::new());
+
+        BuddyAllocator {
+            free_lists: [None; MAX_ORDER + 1],
+            base,
+            frames,
+            free_frame_count: 0,
+            total_frame_count: 0,
+        }
+    }
+
+    /// Whether `frame` is one of the frames that this allocator can manage
+    #[inline(always)]
+    pub fn contains(&self, frame: Frame) -> bool {
+        frame >= self.base && frame.0 < self.base.0 + self.frames.len()
+    }
+
+    #[inline(always)]
+    fn get_frame_info(&self, frame: Frame) -> Option<&FrameInfo> {
+        frame
+            .0
+            .checked_sub(self.base.0)
+            .and_then(|index| self.frames.get(index))
+    }
+
+    #[inline(always)]
+    fn get_frame_info_mut(&mut self, frame: Frame) -> &mut FrameInfo {
+        &mut self.frames[frame.0 - self.base.0]
+    }
+
+    #[inline(always)]
+    pub fn free_frame_count(&self) -> usize {
+        self.free_frame_count
+    }
+
+    #[inline(always)]
+    pub fn total_frame_count(&self) -> usize {
+        self.total_frame_count
+    }
+
+    /// Amount of free blocks of each order
+    pub fn free_block_counts(&self) -> [usize; MAX_ORDER + 1] {
+        core::array::from_fn(|order| {
+            let mut count = 0;
+            let mut block = self.free_lists[order];
+
+            while let Some(current) = block {
+                count += 1;
+                block = unsafe { current.as_ref().next };
+            }
+
+            count
+        })
+    }
+
+    /// Order of the biggest free block, `None` if every frame is used
+    pub fn largest_free_order(&self) -> Option<usize> {
+        (0..=MAX_ORDER)
+            .rev()
+            .find(|&order| self.free_lists[order].is_some())
+    }
+
+    /// Calls `f` with the first frame and the order of every free block
+    pub fn for_each_free_block<F: FnMut(Frame, usize)>(&self, mut f: F) {
+        for (order, &first_block) in self.free_lists.iter().enumerate() {
+            let mut block = first_block;
+
+            while let Some(current) = block {
+                f(Self::get_frame_of(current), order);
+
+                block = unsafe { current.as_ref().next };
+            }
+        }
+    }
+
+    /// Gives a range of frames to the allocator, which were never given to it before
+    pub fn add_range(&mut self, start: Frame, end: Frame) {
+        let start = start.max(self.base);
+        let end = end.min(Frame(self.base.0 + self.frames.len()));
+
+        let mut frame = start;
+
+        while frame < end {
+            // The largest block that is aligned at `frame` and does not go past `end`
+            let order = (frame.0.trailing_zeros() as usize)
+                .min((end.0 - frame.0).ilog2() as usize)
+                .min(MAX_ORDER);
+
+            self.total_frame_count += 1 << order;
+
+            self.free(frame, order);
+
+            frame.0 += 1 << order;
+        }
+    }
+
+    #[inline(always)]
+    fn get_block(frame: Frame) -> NonNull<FreeBlock> {
+        unsafe { NonNull::new_unchecked(frame.virt() as *mut FreeBlock) }
+    }
+
+    #[inline(always)]
+    fn get_frame_of(block: NonNull<FreeBlock>) -> Frame {
+        Frame::from_phys(block.addr().get() - paging::offset(0))
+    }
+
+    fn push_free(&mut self, frame: Frame, order: usize) {
+        let block = Self::get_block(frame);
+
+        unsafe {
+            block.write(FreeBlock {
+                previous: None,
+                next: self.free_lists[order],
+            });
+
+            if let Some(mut next) = self.free_lists[order] {
+                next.as_mut().previous = Some(block);
+            }
+        }
+
+        self.free_lists[order] = Some(block);
+
+        *self.get_frame_info_mut(frame) = FrameInfo {
+            order: order as u8,
+            is_free: true,
+            ref_count: 0,
+        };
+    }
+
+    fn remove_free(&mut self, frame: Frame, order: usize) {
+        let block = Self::get_block(frame);
+
+        unsafe {
+            let FreeBlock { previous, next } = block.read();
+
+            match previous {
+                Some(mut previous) => previous.as_mut().next = next,
+                None => self.free_lists[order] = next,
+            }
+
+            if let Some(mut next) = next {
+                next.as_mut().previous = previous;
+            }
+        }
+
+        self.get_frame_info_mut(frame).is_free = false;
+    }
+
+    /// Allocates a block of 2^order contiguous frames, aligned to its size
+    pub fn alloc(&mut self, order: usize) -> Option<Frame> {
+        let available_order = (order..=MAX_ORDER).find(|&o| self.free_lists[o].is_some())?;
+
+        let block = unsafe { self.free_lists[available_order].unwrap_unchecked() };
+        let frame = Self::get_frame_of(block);
+
+        self.remove_free(frame, available_order);
+
+        // Split the block in halves until it is as small as requested, freeing upper halves
+        for split_order in (order..available_order).rev() {
+            self.push_free(Frame(frame.0 + (1 << split_order)), split_order);
+        }
+
+        let info = self.get_frame_info_mut(frame);
+
+        info.order = order as u8;
+        info.ref_count = 1;
+
+        self.free_frame_count -= 1 << order;
+
+        Some(frame)
+    }
+
+    /// Amount of owners of the allocated block that starts at `frame`
+    #[inline(always)]
+    pub fn get_ref_count(&self, frame: Frame) -> usize {
+        self.get_frame_info(frame)
+            .map_or(0, |info| info.ref_count as usize)
+    }
+
+    /// Adds an owner to the allocated block that starts at `frame`
+    pub fn add_ref(&mut self, frame: Frame) {
+        let info = self.get_frame_info_mut(frame);
+
+        assert!(!info.is_free, "shared a frame that is free");
+
+        info.ref_count = info
+            .ref_count
+            .checked_add(1)
+            .expect("too many owners of a frame");
+    }
+
+    /// Removes an owner from the allocated block that starts at `frame`, returns whether it was
+    /// the last one, then the block must be freed
+    pub fn drop_ref(&mut self, frame: Frame) -> bool {
+        let info = self.get_frame_info_mut(frame);
+
+        assert!(
+            !info.is_free && info.ref_count > 0,
+            "freed a frame that is not allocated"
+        );
+
+        info.ref_count -= 1;
+
+        info.ref_count == 0
+    }
+
+    /// Frees a block of 2^order frames that was returned by `alloc` with the same order, it is
+    /// merged with its buddy for as long as the buddy is free too
+    pub fn free(&mut self, frame: Frame, order: usize) {
+        debug_assert_eq!(frame.0 % (1 << order), 0);
+
+        self.free_frame_count += 1 << order;
+
+        let mut frame = frame;
+        let mut order = order;
+
+        while order < MAX_ORDER {
+            let buddy = Frame(frame.0 ^ (1 << order));
+
+            let is_buddy_free = self
+                .get_frame_info(buddy)
+                .is_some_and(|info| info.is_free && info.order as usize == order);
+
+            if !is_buddy_free {
+                break;
+            }
+
+            self.remove_free(buddy, order);
+
+            frame = frame.min(buddy);
+            order += 1;
+        }
+
+        self.push_free(frame, order);
+    }
+}
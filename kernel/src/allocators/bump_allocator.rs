@@ -0,0 +1,71 @@
+use core::{
+    alloc::Layout,
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[repr(C, align(4096))]
+struct Memory<const SIZE: usize>(UnsafeCell<[u8; SIZE]>);
+
+/// Hands out static memory by bumping an offset, nothing allocated from it is ever freed, so it
+/// is only used for allocations that are few or live until the kernel is going down
+pub struct BumpAllocator<const SIZE: usize> {
+    memory: Memory<SIZE>,
+    used: AtomicUsize,
+}
+
+unsafe impl<const SIZE: usize> Sync for BumpAllocator<SIZE> {}
+
+impl<const SIZE: usize> BumpAllocator<SIZE> {
+    pub const fn new() -> BumpAllocator<SIZE> {
+        BumpAllocator {
+            memory: Memory(UnsafeCell::new([0; SIZE])),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline(always)]
+    fn get_start(&self) -> *mut u8 {
+        self.memory.0.get().cast()
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, ptr: *mut u8) -> bool {
+        let start = self.get_start().addr();
+
+        (start..start + SIZE).contains(&ptr.addr())
+    }
+
+    /// Amount of bytes that were handed out, including the padding for alignment
+    #[inline(always)]
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        let start = self.get_start();
+        let mut used = self.used.load(Ordering::Relaxed);
+
+        loop {
+            let offset = (start.addr() + used).next_multiple_of(layout.align()) - start.addr();
+
+            let Some(end) = offset.checked_add(layout.size()).filter(|&end| end <= SIZE) else {
+                return core::ptr::null_mut();
+            };
+
+            match self
+                .used
+                .compare_exchange_weak(used, end, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return unsafe { start.add(offset) },
+                Err(current) => used = current,
+            }
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for BumpAllocator<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,92 @@
+/// Amount of objects a single magazine can hold
+pub const MAGAZINE_CAPACITY: usize = 32;
+
+/// Amount of objects moved between a magazine and its global allocator at once
+pub const MAGAZINE_BATCH_SIZE: usize = MAGAZINE_CAPACITY / 2;
+
+/// A small stack of free objects owned by a single CPU, so most allocations and deallocations
+/// never touch the global allocator, which is only used to refill or drain it in batches
+pub struct Magazine {
+    objects: [*mut u8; MAGAZINE_CAPACITY],
+    len: usize,
+}
+
+unsafe impl Send for Magazine {}
+
+impl Magazine {
+    pub const fn new() -> Magazine {
+        Magazine {
+            objects: [core::ptr::null_mut(); MAGAZINE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        self.len == MAGAZINE_CAPACITY
+    }
+
+    #[inline(always)]
+    pub fn pop(&mut self) -> Option<*mut u8> {
+        self.len = self.len.checked_sub(1)?;
+
+        Some(self.objects[self.len])
+    }
+
+    /// Returns `false` without taking `object` if the magazine is full
+    #[inline(always)]
+    pub fn push(&mut self, object: *mut u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        self.objects[self.len] = object;
+        self.len += 1;
+
+        true
+    }
+
+    /// Fills the magazine with up to `MAGAZINE_BATCH_SIZE` objects from `alloc`, stopping at
+    /// the first null pointer
+    pub fn refill<F: FnMut() -> *mut u8>(&mut self, mut alloc: F) {
+        for _ in 0..MAGAZINE_BATCH_SIZE {
+            if self.is_full() {
+                break;
+            }
+
+            let object = alloc();
+
+            if object.is_null() {
+                break;
+            }
+
+            self.push(object);
+        }
+    }
+
+    /// Gives up to `count` objects to `dealloc`, the most recently pushed ones first
+    pub fn drain<F: FnMut(*mut u8)>(&mut self, count: usize, mut dealloc: F) {
+        for _ in 0..count {
+            match self.pop() {
+                Some(object) => dealloc(object),
+                None => break,
+            }
+        }
+    }
+}
+
+impl Default for Magazine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1 +1,5 @@
+pub mod buddy_allocator;
+pub mod bump_allocator;
+pub mod magazine;
 pub mod page_allocator;
+pub mod slab_allocator;
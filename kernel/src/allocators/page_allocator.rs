@@ -1,13 +1,16 @@
 use core::{alloc::Layout, cmp::Ordering, ptr::NonNull};
 
-use bit_field::BitField;
-
 use crate::paging::MIN_PAGE_SIZE;
 
-#[derive(Clone, Copy)]
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
 pub struct PageAllocator {
     region_start: NonNull<u8>,
     pub page_count: usize,
+    free_page_count: usize,
+    /// Where the next search starts, right after the last allocation, so we do not keep
+    /// scanning over the pages at the start of the region that are most likely used
+    next_fit_hint: usize,
 }
 
 impl PageAllocator {
@@ -15,9 +18,11 @@ impl PageAllocator {
         // We intentionally use integer division to not overflow the region
         let page_count = region_len / MIN_PAGE_SIZE;
 
-        let page_allocator = PageAllocator {
+        let mut page_allocator = PageAllocator {
             region_start,
             page_count,
+            free_page_count: 0,
+            next_fit_hint: 0,
         };
 
         page_allocator.reserve_bitmap_pages();
@@ -34,30 +39,181 @@ impl PageAllocator {
                 .div_ceil(MIN_PAGE_SIZE)
     }
 
-    fn reserve_bitmap_pages(&self) {
-        let needed_page_count = self.page_count.div_ceil(8).div_ceil(MIN_PAGE_SIZE);
-        (0..needed_page_count).for_each(|i| self.set_free_bit(i, false));
-        (needed_page_count..self.page_count).for_each(|i| self.set_free_bit(i, true));
+    /// Amount of pages at the start of the region that hold the bitmap of `page_count` pages
+    #[inline(always)]
+    pub fn get_bitmap_page_count(page_count: usize) -> usize {
+        page_count
+            .div_ceil(BITS_PER_WORD)
+            .div_ceil(MIN_PAGE_SIZE / size_of::<u64>())
+    }
+
+    fn reserve_bitmap_pages(&mut self) {
+        let needed_page_count = Self::get_bitmap_page_count(self.page_count);
+
+        // Bits past the last page must never look free, so the whole bitmap is cleared first
+        unsafe {
+            self.get_words()
+                .as_ptr()
+                .write_bytes(0, self.page_count.div_ceil(BITS_PER_WORD))
+        };
+
+        self.set_free_bits(needed_page_count..self.page_count, true);
+
+        self.free_page_count = self.page_count.saturating_sub(needed_page_count);
+        self.next_fit_hint = needed_page_count;
     }
 
+    #[inline(always)]
     pub fn calculate_free_space(&self) -> usize {
-        (0..self.page_count)
-            .filter_map(|i| self.is_free(i).then_some(MIN_PAGE_SIZE))
-            .sum()
+        self.free_page_count * MIN_PAGE_SIZE
     }
 
     #[inline(always)]
-    fn is_free(&self, index: usize) -> bool {
-        unsafe { (*self.region_start.byte_add(index / 8).as_ptr()).get_bit(index % 8) }
+    pub fn free_page_count(&self) -> usize {
+        self.free_page_count
     }
 
+    /// The bitmap, where a set bit means the page is free
     #[inline(always)]
-    fn set_free_bit(&self, index: usize, value: bool) {
+    fn get_words(&self) -> NonNull<u64> {
+        self.region_start.cast()
+    }
+
+    #[inline(always)]
+    fn get_word(&self, word_index: usize) -> u64 {
+        unsafe { self.get_words().add(word_index).read() }
+    }
+
+    #[inline(always)]
+    fn update_word(&self, word_index: usize, mask: u64, value: bool) {
         unsafe {
-            (*self.region_start.byte_add(index / 8).as_ptr()).set_bit(index % 8, value);
+            let word = self.get_words().add(word_index).as_ptr();
+
+            if value {
+                *word |= mask;
+            } else {
+                *word &= !mask;
+            }
         }
     }
 
+    /// Mask of the bits of a word that are in `start..end`, relative to the start of the word
+    #[inline(always)]
+    fn get_mask(start: usize, end: usize) -> u64 {
+        let len = end - start;
+
+        if len == BITS_PER_WORD {
+            u64::MAX
+        } else {
+            ((1 << len) - 1) << start
+        }
+    }
+
+    fn set_free_bits(&self, page_indices: core::ops::Range<usize>, value: bool) {
+        let mut index = page_indices.start;
+
+        while index < page_indices.end {
+            let word_index = index / BITS_PER_WORD;
+            let word_end = ((word_index + 1) * BITS_PER_WORD).min(page_indices.end);
+
+            self.update_word(
+                word_index,
+                Self::get_mask(index % BITS_PER_WORD, word_end - word_index * BITS_PER_WORD),
+                value,
+            );
+
+            index = word_end;
+        }
+    }
+
+    /// The first free page at or after `index`, skipping whole words of used pages
+    fn find_free(&self, index: usize) -> Option<usize> {
+        if index >= self.page_count {
+            return None;
+        }
+
+        let mut word_index = index / BITS_PER_WORD;
+        let mut word = self.get_word(word_index) & (u64::MAX << (index % BITS_PER_WORD));
+
+        loop {
+            if word != 0 {
+                let free_index = word_index * BITS_PER_WORD + word.trailing_zeros() as usize;
+
+                return (free_index < self.page_count).then_some(free_index);
+            }
+
+            word_index += 1;
+
+            if word_index * BITS_PER_WORD >= self.page_count {
+                return None;
+            }
+
+            word = self.get_word(word_index);
+        }
+    }
+
+    /// The first used page in `page_indices`, or `None` if all of them are free
+    fn find_used(&self, page_indices: core::ops::Range<usize>) -> Option<usize> {
+        let mut index = page_indices.start;
+
+        while index < page_indices.end {
+            let word_index = index / BITS_PER_WORD;
+            let word_end = ((word_index + 1) * BITS_PER_WORD).min(page_indices.end);
+
+            let mask = Self::get_mask(index % BITS_PER_WORD, word_end - word_index * BITS_PER_WORD);
+            let used = !self.get_word(word_index) & mask;
+
+            if used != 0 {
+                return Some(word_index * BITS_PER_WORD + used.trailing_zeros() as usize);
+            }
+
+            index = word_end;
+        }
+
+        None
+    }
+
+    /// The first run of `count` free pages that starts at or after `index` and at a page index
+    /// that is `first_index` plus a multiple of `step`
+    fn find_free_run(
+        &self,
+        mut index: usize,
+        count: usize,
+        first_index: usize,
+        step: usize,
+    ) -> Option<usize> {
+        loop {
+            index = self.find_free(index.max(first_index))?;
+            index = first_index + (index - first_index).next_multiple_of(step);
+
+            if index + count > self.page_count {
+                return None;
+            }
+
+            match self.find_used(index..index + count) {
+                Some(used_index) => index = used_index + 1,
+                None => return Some(index),
+            }
+        }
+    }
+
+    /// Length in pages of the longest run of free pages
+    pub fn find_largest_free_run(&self) -> usize {
+        let mut largest_run = 0;
+        let mut index = 0;
+
+        while let Some(free_index) = self.find_free(index) {
+            let used_index = self
+                .find_used(free_index..self.page_count)
+                .unwrap_or(self.page_count);
+
+            largest_run = largest_run.max(used_index - free_index);
+            index = used_index;
+        }
+
+        largest_run
+    }
+
     #[inline(always)]
     fn get_page(&self, index: usize) -> NonNull<u8> {
         unsafe { self.region_start.byte_add(index * MIN_PAGE_SIZE) }
@@ -81,14 +237,71 @@ impl PageAllocator {
         }
     }
 
-    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+    /// Allocates enough contiguous pages for `layout`, the first page is aligned to
+    /// `layout.align()`, which only works for alignments the region itself is aligned to
+    pub fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let needed_page_count = layout.size().div_ceil(MIN_PAGE_SIZE);
+
+        if needed_page_count > self.free_page_count {
+            return core::ptr::null_mut();
+        }
+
+        // Only every `page_step`th page is aligned enough, starting at `first_page_index`
+        let page_step = (layout.align() / MIN_PAGE_SIZE).max(1);
+        let first_page_index = self
+            .region_start
+            .align_offset(layout.align())
+            .div_ceil(MIN_PAGE_SIZE);
+
+        // Next fit, and only if nothing is found after the hint we go back to the start
+        let page_index = self
+            .find_free_run(
+                self.next_fit_hint,
+                needed_page_count,
+                first_page_index,
+                page_step,
+            )
+            .or_else(|| self.find_free_run(0, needed_page_count, first_page_index, page_step));
+
+        match page_index {
+            Some(page_index) => {
+                self.set_free_bits(page_index..(page_index + needed_page_count), false);
+
+                self.free_page_count -= needed_page_count;
+                self.next_fit_hint = page_index + needed_page_count;
+
+                self.get_page(page_index).as_ptr()
+            }
+
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    /// The scan that `alloc` replaced, which checks every page of every candidate run bit by
+    /// bit from the start of the region, it is only kept to be compared with in the benchmarks
+    #[cfg(feature = "benchmarks")]
+    pub fn alloc_bit_by_bit(&mut self, layout: Layout) -> *mut u8 {
         let needed_page_count = layout.size().div_ceil(MIN_PAGE_SIZE);
 
-        for page_index in 0..self.page_count {
+        if needed_page_count > self.page_count {
+            return core::ptr::null_mut();
+        }
+
+        let page_step = (layout.align() / MIN_PAGE_SIZE).max(1);
+        let first_page_index = self
+            .region_start
+            .align_offset(layout.align())
+            .div_ceil(MIN_PAGE_SIZE);
+
+        let last_page_index = self.page_count - needed_page_count;
+
+        for page_index in (first_page_index..=last_page_index).step_by(page_step) {
             let page_indices = page_index..(page_index + needed_page_count);
 
             if page_indices.clone().all(|i| self.is_free(i)) {
-                page_indices.for_each(|i| self.set_free_bit(i, false));
+                page_indices.for_each(|i| self.set_free_bits(i..i + 1, false));
+
+                self.free_page_count -= needed_page_count;
 
                 return self.get_page(page_index).as_ptr();
             }
@@ -97,17 +310,25 @@ impl PageAllocator {
         core::ptr::null_mut()
     }
 
+    #[cfg(feature = "benchmarks")]
+    #[inline(always)]
+    fn is_free(&self, index: usize) -> bool {
+        self.get_word(index / BITS_PER_WORD) & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
     #[inline]
-    pub fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    pub fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         let needed_page_count = layout.size().div_ceil(MIN_PAGE_SIZE);
         let page_index = self.get_page_index_of(ptr);
-        let page_indices = page_index..(page_index + needed_page_count);
-        page_indices.for_each(|i| self.set_free_bit(i, true));
+
+        self.set_free_bits(page_index..(page_index + needed_page_count), true);
+
+        self.free_page_count += needed_page_count;
     }
 
     /// Tries to resize the allocation without reallocating, returns whether the resize is
     /// successful, otherwise an allocation must be done
-    pub fn resize(&self, old_ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
+    pub fn resize(&mut self, old_ptr: *mut u8, layout: Layout, new_size: usize) -> bool {
         let old_needed_page_count = layout.size().div_ceil(MIN_PAGE_SIZE);
         let new_needed_page_count = new_size.div_ceil(MIN_PAGE_SIZE);
 
@@ -120,9 +341,11 @@ impl PageAllocator {
                 let page_index = self.get_page_index_of(old_ptr);
 
                 let page_indices =
-                    (page_index + old_needed_page_count)..(page_index + new_needed_page_count);
+                    (page_index + new_needed_page_count)..(page_index + old_needed_page_count);
+
+                self.free_page_count += page_indices.len();
 
-                page_indices.for_each(|i| self.set_free_bit(i, true));
+                self.set_free_bits(page_indices, true);
 
                 true
             }
@@ -135,9 +358,13 @@ impl PageAllocator {
                 let page_indices =
                     (page_index + old_needed_page_count)..(page_index + new_needed_page_count);
 
-                if page_indices.clone().all(|i| self.is_free(i)) {
+                let is_in_region = page_indices.end <= self.page_count;
+
+                if is_in_region && self.find_used(page_indices.clone()).is_none() {
                     // We can expand our memory :)
-                    page_indices.for_each(|i| self.set_free_bit(i, false));
+                    self.free_page_count -= page_indices.len();
+
+                    self.set_free_bits(page_indices, false);
 
                     true
                 } else {
@@ -0,0 +1,223 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::NonNull,
+};
+
+use crate::paging::MIN_PAGE_SIZE;
+
+/// Object sizes served by slabs, anything bigger is served by whole pages
+pub const SIZE_CLASSES: [usize; 8] = [8, 16, 32, 64, 128, 256, 512, 1024];
+
+pub const MAX_SLAB_OBJECT_SIZE: usize = SIZE_CLASSES[SIZE_CLASSES.len() - 1];
+
+/// Get the index of the smallest size class that fits `layout`, objects are aligned to their
+/// size, so the alignment is also considered
+#[inline(always)]
+pub fn get_size_class_index(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(SIZE_CLASSES[0]);
+
+    (size <= MAX_SLAB_OBJECT_SIZE)
+        .then(|| size.next_power_of_two().trailing_zeros() - SIZE_CLASSES[0].trailing_zeros())
+        .map(|index| index as usize)
+}
+
+/// A free object, which stores a pointer to the next free object in the same slab
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// Lives at the start of every slab, which is exactly one page, so the header of an object can
+/// be found by aligning it down to the page size
+struct SlabHeader {
+    previous: Option<NonNull<SlabHeader>>,
+    next: Option<NonNull<SlabHeader>>,
+    free_objects: Option<NonNull<FreeObject>>,
+    used_count: usize,
+}
+
+impl SlabHeader {
+    #[inline(always)]
+    fn of(object: NonNull<u8>) -> NonNull<SlabHeader> {
+        let slab = object.as_ptr().map_addr(|addr| addr - addr % MIN_PAGE_SIZE);
+
+        unsafe { NonNull::new_unchecked(slab.cast()) }
+    }
+}
+
+/// Hands out objects of a single size class from slabs that have free objects
+pub struct SlabCache {
+    object_size: usize,
+    /// Slabs that have at least one free object, full slabs are not tracked at all
+    partial_slabs: Option<NonNull<SlabHeader>>,
+    /// Amount of slabs owned by this cache, full or not
+    pub slab_count: usize,
+    /// Amount of objects currently handed out
+    pub used_count: usize,
+}
+
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    pub const fn new(object_size: usize) -> SlabCache {
+        SlabCache {
+            object_size,
+            partial_slabs: None,
+            slab_count: 0,
+            used_count: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    /// Amount of objects that fit in a single slab after its header
+    #[inline(always)]
+    pub fn objects_per_slab(&self) -> usize {
+        (MIN_PAGE_SIZE - self.first_object_offset()) / self.object_size
+    }
+
+    #[inline(always)]
+    fn first_object_offset(&self) -> usize {
+        size_of::<SlabHeader>().next_multiple_of(self.object_size)
+    }
+
+    #[inline(always)]
+    fn slab_layout() -> Layout {
+        unsafe { Layout::from_size_align_unchecked(MIN_PAGE_SIZE, MIN_PAGE_SIZE) }
+    }
+
+    /// Takes a page from `pages` and threads all of its objects into a free list
+    fn grow<A: GlobalAlloc>(&mut self, pages: &A) -> Option<NonNull<SlabHeader>> {
+        let page = NonNull::new(unsafe { pages.alloc(Self::slab_layout()) })?;
+
+        let mut free_objects = None;
+
+        for index in (0..self.objects_per_slab()).rev() {
+            let object = unsafe {
+                page.byte_add(self.first_object_offset() + index * self.object_size)
+                    .cast::<FreeObject>()
+            };
+
+            unsafe { object.write(FreeObject { next: free_objects }) };
+
+            free_objects = Some(object);
+        }
+
+        let slab = page.cast::<SlabHeader>();
+
+        unsafe {
+            slab.write(SlabHeader {
+                previous: None,
+                next: None,
+                free_objects,
+                used_count: 0,
+            })
+        };
+
+        self.slab_count += 1;
+
+        Some(slab)
+    }
+
+    fn push_partial(&mut self, mut slab: NonNull<SlabHeader>) {
+        unsafe {
+            slab.as_mut().previous = None;
+            slab.as_mut().next = self.partial_slabs;
+
+            if let Some(mut next) = self.partial_slabs {
+                next.as_mut().previous = Some(slab);
+            }
+        }
+
+        self.partial_slabs = Some(slab);
+    }
+
+    fn remove_partial(&mut self, slab: NonNull<SlabHeader>) {
+        unsafe {
+            let SlabHeader { previous, next, .. } = *slab.as_ptr();
+
+            match previous {
+                Some(mut previous) => previous.as_mut().next = next,
+                None => self.partial_slabs = next,
+            }
+
+            if let Some(mut next) = next {
+                next.as_mut().previous = previous;
+            }
+        }
+    }
+
+    pub fn alloc<A: GlobalAlloc>(&mut self, pages: &A) -> *mut u8 {
+        let mut slab = match self.partial_slabs {
+            Some(slab) => slab,
+
+            None => match self.grow(pages) {
+                Some(slab) => {
+                    self.push_partial(slab);
+                    slab
+                }
+
+                None => return core::ptr::null_mut(),
+            },
+        };
+
+        let slab_header = unsafe { slab.as_mut() };
+
+        // A partial slab always has at least one free object
+        let object = unsafe { slab_header.free_objects.unwrap_unchecked() };
+
+        slab_header.free_objects = unsafe { object.as_ref().next };
+        slab_header.used_count += 1;
+
+        if slab_header.free_objects.is_none() {
+            self.remove_partial(slab);
+        }
+
+        self.used_count += 1;
+
+        object.as_ptr().cast()
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc` of this cache with the same `pages`
+    pub unsafe fn dealloc<A: GlobalAlloc>(&mut self, ptr: *mut u8, pages: &A) {
+        let object = unsafe { NonNull::new_unchecked(ptr) }.cast::<FreeObject>();
+        let mut slab = SlabHeader::of(object.cast());
+
+        let (was_full, is_empty) = unsafe {
+            let slab_header = slab.as_mut();
+
+            let was_full = slab_header.free_objects.is_none();
+
+            object.write(FreeObject {
+                next: slab_header.free_objects,
+            });
+
+            slab_header.free_objects = Some(object);
+            slab_header.used_count -= 1;
+
+            (was_full, slab_header.used_count == 0)
+        };
+
+        self.used_count -= 1;
+
+        if was_full {
+            self.push_partial(slab);
+        }
+
+        // Give empty slabs back, unless it is the only one we have left to allocate from
+        let has_other_partial_slabs =
+            self.partial_slabs != Some(slab) || unsafe { slab.as_ref().next.is_some() };
+
+        if is_empty && has_other_partial_slabs {
+            self.remove_partial(slab);
+
+            unsafe { pages.dealloc(slab.as_ptr().cast(), Self::slab_layout()) };
+
+            self.slab_count -= 1;
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use core::arch::asm;
+
 use spin::Mutex;
 
 use crate::mp::MAX_CPU_COUNT;
@@ -31,3 +33,82 @@ impl Cpu {
             .write((&mut cpus[cpu.id as usize]) as *mut _ as usize as u64);
     }
 }
+
+/// Whether level 3 page table entries can map 1 GiB pages
+pub fn supports_1gib_pages() -> bool {
+    let extended_features = core::arch::x86_64::__cpuid(0x8000_0001);
+
+    extended_features.edx & (1 << 26) != 0
+}
+
+/// Whether the memory type of pages can be chosen through the PAT
+pub fn supports_pat() -> bool {
+    let features = core::arch::x86_64::__cpuid(0x1);
+
+    features.edx & (1 << 16) != 0
+}
+
+/// Whether pages can be marked as not executable
+pub fn supports_no_execute() -> bool {
+    let extended_features = core::arch::x86_64::__cpuid(0x8000_0001);
+
+    extended_features.edx & (1 << 20) != 0
+}
+
+/// The id of the CPU we are running on, or `None` if `Cpu::set` was not called on it yet
+pub fn get_current_id() -> Option<usize> {
+    let cpu = ModelSpecificRegister::KernelGsBase.read() as *const Cpu;
+
+    (!cpu.is_null()).then(|| unsafe { (*cpu).id as usize })
+}
+
+/// Cycles counted by the CPU we are running on, which is only meaningful for measuring how long
+/// something took on the same CPU
+#[inline(always)]
+pub fn read_timestamp_counter() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Moves the CPU we are running on to the stack that ends at `stack_end`, and calls `f` with
+/// `argument` there, the current stack is never returned to, so it can be freed
+pub fn switch_stack(stack_end: usize, f: extern "C" fn(usize) -> !, argument: usize) -> ! {
+    // A zero frame pointer ends the chain that `get_return_addresses` follows
+    unsafe {
+        asm!(
+            "mov rsp, {}",
+            "xor ebp, ebp",
+            "call {}",
+            in(reg) stack_end,
+            in(reg) f,
+            in("rdi") argument,
+            options(noreturn)
+        )
+    }
+}
+
+/// The return addresses of the functions that lead to the current one, most recent first,
+/// found by following the saved frame pointers, so the kernel must be built with frame
+/// pointers for them to be meaningful, missing frames are left as zero
+#[inline(always)]
+pub fn get_return_addresses<const N: usize>() -> [usize; N] {
+    let mut return_addresses = [0; N];
+    let mut frame_pointer: *const usize;
+
+    unsafe {
+        asm!("mov {}, rbp", out(reg) frame_pointer, options(nomem, nostack, preserves_flags))
+    };
+
+    for return_address in return_addresses.iter_mut() {
+        // Frame pointers always point into the higher half, anything else ends the chain
+        if frame_pointer.addr() < 0xffff_8000_0000_0000 || !frame_pointer.is_aligned() {
+            break;
+        }
+
+        unsafe {
+            *return_address = *frame_pointer.add(1);
+            frame_pointer = *frame_pointer as *const usize;
+        }
+    }
+
+    return_addresses
+}
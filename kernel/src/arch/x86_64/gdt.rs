@@ -136,8 +136,8 @@ impl Descriptor {
     }
 
     #[inline]
-    pub fn task_state_segment(tss: &'static TaskStateSegment) -> Descriptor {
-        let ptr = tss as *const _ as u64;
+    pub fn task_state_segment(tss: *const TaskStateSegment) -> Descriptor {
+        let ptr = tss as u64;
 
         let mut low = flags::PRESENT;
         let mut high = 0;
@@ -167,7 +167,7 @@ lazy_static! {
         gdt.push(Descriptor::user_data_segment()); // 0x20
 
         for cpu_id in 0..MAX_CPU_COUNT {
-            gdt.push(Descriptor::task_state_segment(&TSS[cpu_id])); // 0x28 + (cpu_id * 16)
+            gdt.push(Descriptor::task_state_segment(TSS.get(cpu_id))); // 0x28 + (cpu_id * 16)
         }
 
         gdt
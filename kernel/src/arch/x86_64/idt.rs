@@ -3,7 +3,15 @@ use core::arch::asm;
 use bit_field::BitField;
 use lazy_static::lazy_static;
 
-use crate::arch::local_apic;
+use crate::{
+    arch::{
+        cpu, interrupts, keyboard,
+        local_apic::{self, LocalApic, LocalApicRegister},
+        tlb, tss,
+    },
+    memory::{self, PageFaultError},
+    paging::{self, PageFault},
+};
 
 use super::DescriptorTableRegister;
 
@@ -113,7 +121,7 @@ lazy_static! {
 
         idt.table[8]
             .set_handler_address(handle_double_fault as usize as u64)
-            .set_stack_index(0);
+            .set_stack_index(tss::DOUBLE_FAULT_IST_INDEX);
 
         idt.table[10].set_handler_address(handle_segmentation_fault as usize as u64);
         idt.table[11].set_handler_address(handle_segmentation_fault as usize as u64);
@@ -131,6 +139,15 @@ lazy_static! {
         idt.table[30].set_handler_address(handle_security_exception as usize as u64);
         idt.table[32].set_handler_address(local_apic::handle_timer_tick as usize as u64);
 
+        idt.table[tlb::SHOOTDOWN_VECTOR as usize]
+            .set_handler_address(handle_tlb_shootdown as usize as u64);
+
+        idt.table[keyboard::KEYBOARD_VECTOR as usize]
+            .set_handler_address(handle_keyboard as usize as u64);
+
+        idt.table[local_apic::SPURIOUS_VECTOR as usize]
+            .set_handler_address(handle_spurious_interrupt as usize as u64);
+
         idt
     };
 }
@@ -170,6 +187,12 @@ extern "x86-interrupt" fn handle_device_not_available(_: InterruptStackFrame) {
 }
 
 extern "x86-interrupt" fn handle_double_fault(_: InterruptStackFrame, code: u64) {
+    // A stack overflow faults, and the CPU can not push the fault's stack frame onto the
+    // overflowed stack, which faults again and becomes a double fault
+    if let Some(stack_name) = memory::find_overflowed_stack(paging::get_fault_address()) {
+        report_stack_overflow(stack_name);
+    }
+
     panic!("double fault: {}", code);
 }
 
@@ -181,8 +204,35 @@ extern "x86-interrupt" fn handle_general_protection_fault(_: InterruptStackFrame
     panic!("general protection fault: {}", code);
 }
 
-extern "x86-interrupt" fn handle_page_fault(_: InterruptStackFrame, code: u64) {
-    panic!("page fault: {}", code);
+extern "x86-interrupt" fn handle_page_fault(stack_frame: InterruptStackFrame, code: u64) {
+    let fault = PageFault::from_code(code);
+
+    // Handling the fault may wait for a CPU that waits for us to take a shootdown
+    if stack_frame.rflags & (1 << 9) != 0 {
+        interrupts::enable();
+    }
+
+    match memory::handle_page_fault(&fault) {
+        Ok(()) => {}
+
+        Err(PageFaultError::StackOverflow(stack_name)) => report_stack_overflow(stack_name),
+
+        Err(error) => panic!(
+            "page fault: {} {} of {:#x} at {:#x}, {}",
+            if fault.is_user { "user" } else { "kernel" },
+            fault.access_as_str(),
+            fault.address,
+            stack_frame.rip,
+            error
+        ),
+    }
+}
+
+fn report_stack_overflow(stack_name: &str) -> ! {
+    match cpu::get_current_id() {
+        Some(cpu_id) => panic!("stack overflow on cpu {cpu_id} in its {stack_name}"),
+        None => panic!("stack overflow in the {stack_name}"),
+    }
 }
 
 extern "x86-interrupt" fn handle_x87_floating_point_exception(_: InterruptStackFrame) {
@@ -220,3 +270,18 @@ extern "x86-interrupt" fn handle_vmm_communication_exception(_: InterruptStackFr
 extern "x86-interrupt" fn handle_security_exception(_: InterruptStackFrame, code: u64) {
     panic!("security exception: {}", code);
 }
+
+extern "x86-interrupt" fn handle_tlb_shootdown(_: InterruptStackFrame) {
+    tlb::handle_shootdown();
+
+    LocalApic::get().write(LocalApicRegister::Eoi, 0);
+}
+
+extern "x86-interrupt" fn handle_keyboard(_: InterruptStackFrame) {
+    keyboard::handle_interrupt();
+
+    LocalApic::get().write(LocalApicRegister::Eoi, 0);
+}
+
+/// Spurious interrupts must not be acknowledged
+extern "x86-interrupt" fn handle_spurious_interrupt(_: InterruptStackFrame) {}
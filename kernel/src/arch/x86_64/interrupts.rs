@@ -12,6 +12,17 @@ pub fn enable() {
     }
 }
 
+pub fn are_enabled() -> bool {
+    let rflags: u64;
+
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) rflags, options(preserves_flags));
+    }
+
+    // The interrupt flag
+    rflags & (1 << 9) != 0
+}
+
 pub fn wait_for_interrupts() {
     unsafe {
         asm!("hlt");
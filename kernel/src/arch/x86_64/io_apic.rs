@@ -2,34 +2,37 @@ use spin::Mutex;
 
 use crate::{
     acpi::ACPI,
-    memory::align_down,
-    paging::{self, MIN_PAGE_SIZE},
+    memory::{self, Mmio},
+    paging::CacheType,
 };
 
 pub const MAX_IO_APIC_COUNT: usize = 128;
 
 pub static IO_APIC_COUNT: Mutex<usize> = Mutex::new(0);
 
-pub static IO_APICS: Mutex<[IoApic; MAX_IO_APIC_COUNT]> =
-    Mutex::new([const { IoApic(0) }; MAX_IO_APIC_COUNT]);
+pub static IO_APICS: Mutex<[Option<IoApic>; MAX_IO_APIC_COUNT]> =
+    Mutex::new([const { None }; MAX_IO_APIC_COUNT]);
 
+/// Selects the register that is accessed through the window
+const IOREGSEL: usize = 0x0;
 
-#[derive(Debug, Clone, Copy)]
-pub struct IoApic(usize);
+/// Reads and writes the selected register
+const IOWIN: usize = 0x10;
+
+const IO_APIC_SIZE: usize = 0x20;
+
+#[derive(Debug)]
+pub struct IoApic(Mmio);
 
 impl IoApic {
     fn read(&self, index: u32) -> u32 {
-        unsafe {
-            (self.0 as *mut u32).write_volatile(index);
-            ((self.0 + 0x10) as *const u32).read_volatile()
-        }
+        self.0.write(IOREGSEL, index);
+        self.0.read(IOWIN)
     }
 
     fn write(&self, index: u32, value: u32) {
-        unsafe {
-            (self.0 as *mut u32).write_volatile(index);
-            ((self.0 + 0x10) as *mut u32).write_volatile(value);
-        }
+        self.0.write(IOREGSEL, index);
+        self.0.write(IOWIN, value);
     }
 
     pub fn id(&self) -> u32 {
@@ -89,18 +92,16 @@ pub fn init() {
 
     for io_apic_entry in madt.io_apic_iter().take(MAX_IO_APIC_COUNT) {
         let io_apic_phys_addr = io_apic_entry.physical_address as usize;
-        let io_apic_virt_addr = paging::offset(io_apic_phys_addr);
-
-        paging::get_active_table()
-            .map(
-                align_down(io_apic_virt_addr, MIN_PAGE_SIZE),
-                align_down(io_apic_phys_addr, MIN_PAGE_SIZE),
-            )
-            .set_writable(true)
-            .set_write_through(true)
-            .set_cachability(false);
-
-        io_apics[*io_apic_count].0 = io_apic_virt_addr;
+
+        let mmio = memory::ioremap(
+            io_apic_phys_addr,
+            IO_APIC_SIZE,
+            CacheType::Uncacheable,
+            "i/o apic",
+        )
+        .expect("could not map an I/O APIC");
+
+        io_apics[*io_apic_count] = Some(IoApic(mmio));
 
         *io_apic_count += 1;
     }
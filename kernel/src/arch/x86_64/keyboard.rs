@@ -0,0 +1,88 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::{io_apic::IO_APICS, io_ports::inb, local_apic::LocalApic};
+
+/// The interrupt that the keyboard raises when a key is pressed or released
+pub const KEYBOARD_VECTOR: u8 = 34;
+
+/// The keyboard is the second ISA interrupt, which PCs wire to the same input of the first I/O
+/// APIC
+const KEYBOARD_IRQ: u32 = 1;
+
+const DATA_PORT: u16 = 0x60;
+
+/// The first bit of the status tells whether there is a scancode to read
+const STATUS_PORT: u16 = 0x64;
+
+/// A missing controller reads as all ones, so the scancodes left by the bootloader are drained
+/// at most this many times
+const MAX_DRAINED_SCANCODE_COUNT: usize = 16;
+
+/// Scancodes of set 1, which the controller translates every keyboard to by default, the right
+/// Alt key sends the same scancodes after a prefix
+const ALT_PRESSED: u8 = 0x38;
+const ALT_RELEASED: u8 = 0xb8;
+const F1_PRESSED: u8 = 0x3b;
+const F10_PRESSED: u8 = 0x44;
+const F11_PRESSED: u8 = 0x57;
+const F12_PRESSED: u8 = 0x58;
+
+const NO_REQUESTED_TERMINAL: usize = usize::MAX;
+
+static IS_ALT_PRESSED: AtomicBool = AtomicBool::new(false);
+
+/// The virtual terminal that was asked for with Alt+F1..F12, which is switched to outside of the
+/// interrupt
+static REQUESTED_TERMINAL: AtomicUsize = AtomicUsize::new(NO_REQUESTED_TERMINAL);
+
+/// Routes the keyboard interrupt to the CPU we are running on
+pub fn init() {
+    // The keyboard does not raise the interrupt again until the scancodes it sent are read
+    for _ in 0..MAX_DRAINED_SCANCODE_COUNT {
+        if inb(STATUS_PORT) & 1 == 0 {
+            break;
+        }
+
+        inb(DATA_PORT);
+    }
+
+    let io_apics = IO_APICS.lock();
+
+    let io_apic = io_apics[0].as_ref().expect("no I/O APICs found");
+
+    io_apic.irq_set(KEYBOARD_IRQ, LocalApic::get().id(), KEYBOARD_VECTOR as u32);
+}
+
+/// Reads the scancode that raised the keyboard interrupt, called when it is received
+pub fn handle_interrupt() {
+    let scancode = inb(DATA_PORT);
+
+    let is_alt_pressed = IS_ALT_PRESSED.load(Ordering::Relaxed);
+
+    let terminal = match scancode {
+        ALT_PRESSED => {
+            IS_ALT_PRESSED.store(true, Ordering::Relaxed);
+            return;
+        }
+
+        ALT_RELEASED => {
+            IS_ALT_PRESSED.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        F1_PRESSED..=F10_PRESSED if is_alt_pressed => scancode - F1_PRESSED,
+        F11_PRESSED | F12_PRESSED if is_alt_pressed => scancode - F11_PRESSED + 10,
+
+        _ => return,
+    };
+
+    REQUESTED_TERMINAL.store(terminal as usize, Ordering::Release);
+}
+
+/// The virtual terminal that was asked for with Alt+F1..F12 since the last call, switching to
+/// it locks terminals, which the CPU may have been holding when the keyboard interrupted it
+pub fn take_requested_terminal() -> Option<usize> {
+    let terminal = REQUESTED_TERMINAL.swap(NO_REQUESTED_TERMINAL, Ordering::AcqRel);
+
+    (terminal != NO_REQUESTED_TERMINAL).then_some(terminal)
+}
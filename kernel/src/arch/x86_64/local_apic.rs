@@ -1,65 +1,110 @@
-use spin::mutex::Mutex;
+use spin::Once;
 
-use crate::{memory, mp::MAX_CPU_COUNT, paging};
+use crate::{
+    memory::{self, Mmio},
+    mp::MAX_CPU_COUNT,
+    paging::{CacheType, MIN_PAGE_SIZE},
+};
 
 use super::{cpu::Cpu, msr::ModelSpecificRegister};
 
-static LOCAL_APICS: Mutex<[LocalApic; MAX_CPU_COUNT]> =
-    Mutex::new([const { LocalApic(0) }; MAX_CPU_COUNT]);
+/// The interrupt that the local APIC raises when an interrupt went away before it was handled
+pub const SPURIOUS_VECTOR: u8 = 0xff;
 
-#[derive(Clone, Copy)]
-pub struct LocalApic(usize);
+static LOCAL_APICS: [Once<LocalApic>; MAX_CPU_COUNT] = [const { Once::new() }; MAX_CPU_COUNT];
+
+pub struct LocalApic {
+    registers: Mmio,
+    /// The id that other CPUs send interrupts to, it is read once, as every CPU sees its own
+    /// local APIC at the same address
+    id: u32,
+}
 
 #[repr(usize)]
 pub enum LocalApicRegister {
     Id = 0x20,
     Version = 0x30,
     Eoi = 0xb0,
+    SpuriousInterruptVector = 0xf0,
+    InterruptCommandLow = 0x300,
+    InterruptCommandHigh = 0x310,
     TimerLvt = 0x320,
     TimerInit = 0x380,
     TimerDiv = 0x3e0,
 }
 
 impl LocalApic {
-    pub fn get() -> LocalApic {
-        LOCAL_APICS.lock()[Cpu::get().id as usize]
+    pub fn get() -> &'static LocalApic {
+        LOCAL_APICS[Cpu::get().id as usize]
+            .get()
+            .expect("the local apic of this cpu is not initialized")
+    }
+
+    /// The local APIC of the CPU with the id `cpu_id`, or `None` if it is not initialized
+    pub fn get_of(cpu_id: usize) -> Option<&'static LocalApic> {
+        LOCAL_APICS.get(cpu_id)?.get()
+    }
+
+    #[inline(always)]
+    pub fn id(&self) -> u32 {
+        self.id
     }
 
     pub fn write(&self, register: LocalApicRegister, value: u32) {
-        unsafe {
-            ((self.0 + register as usize) as *mut u32).write_volatile(value);
-        }
+        self.registers.write(register as usize, value);
     }
 
     pub fn read(&self, register: LocalApicRegister) -> u32 {
-        unsafe { ((self.0 + register as usize) as *mut u32).read_volatile() }
+        self.registers.read(register as usize)
+    }
+
+    /// Sends the interrupt `vector` to the CPU whose local APIC has the id `destination`, and
+    /// waits until it is delivered
+    pub fn send_ipi(&self, destination: u32, vector: u8) {
+        self.write(LocalApicRegister::InterruptCommandHigh, destination << 24);
+
+        // Fixed delivery to a physical destination, the level must be assert for fixed delivery
+        self.write(
+            LocalApicRegister::InterruptCommandLow,
+            vector as u32 | (1 << 14),
+        );
+
+        // The delivery status
+        while self.read(LocalApicRegister::InterruptCommandLow) & (1 << 12) != 0 {
+            core::hint::spin_loop();
+        }
     }
 }
 
 pub fn init() {
     let cpu = Cpu::get();
 
-    let mut local_apics = LOCAL_APICS.lock();
-
-    let local_apic = &mut local_apics[cpu.id as usize];
-
     let apic_base_msr = ModelSpecificRegister::ApicBase.read();
 
     let local_apic_phys_addr = apic_base_msr as usize & 0xFFFFF000;
-    let local_apic_virt_addr = paging::offset(local_apic_phys_addr);
 
-    paging::get_active_table()
-        .map(
-            memory::align_down(local_apic_virt_addr, paging::MIN_PAGE_SIZE),
-            memory::align_down(local_apic_phys_addr, paging::MIN_PAGE_SIZE),
+    // The registers can only be accessed once the local APIC is enabled
+    ModelSpecificRegister::ApicBase.write(apic_base_msr | (1 << 11));
+
+    let local_apic = LOCAL_APICS[cpu.id as usize].call_once(|| {
+        let registers = memory::ioremap(
+            local_apic_phys_addr,
+            MIN_PAGE_SIZE,
+            CacheType::Uncacheable,
+            "local apic",
         )
-        .set_writable(true)
-        .set_write_through(true)
-        .set_cachability(false);
+        .expect("could not map the local apic");
 
-    local_apic.0 = local_apic_virt_addr;
+        let id = registers.read::<u32>(LocalApicRegister::Id as usize) >> 24;
 
-    ModelSpecificRegister::ApicBase.write(apic_base_msr | (1 << 11));
+        LocalApic { registers, id }
+    });
+
+    // Software enable the local APIC, so it delivers interrupts from other CPUs
+    local_apic.write(
+        LocalApicRegister::SpuriousInterruptVector,
+        SPURIOUS_VECTOR as u32 | (1 << 8),
+    );
 
     local_apic.write(LocalApicRegister::TimerInit, 0x19FBD0);
     local_apic.write(LocalApicRegister::TimerLvt, 32 | (1 << 17));
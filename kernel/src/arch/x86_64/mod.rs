@@ -4,14 +4,23 @@ pub mod idt;
 pub mod interrupts;
 pub mod io_apic;
 pub mod io_ports;
+pub mod keyboard;
 pub mod local_apic;
 pub mod msr;
 pub mod paging;
 pub mod pic;
+pub mod sections;
+pub mod serial;
+pub mod tlb;
 pub mod tss;
 
 use cpu::Cpu;
 
+use crate::splash;
+
+/// Amount of times `init_bsp` advances the boot splash
+pub const INIT_STAGE_COUNT: usize = 8;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed(2))]
 struct DescriptorTableRegister {
@@ -22,12 +31,22 @@ struct DescriptorTableRegister {
 pub fn init_bsp() {
     Cpu::set(Cpu::new(0));
 
+    serial::init();
+    splash::advance();
     gdt::load();
+    splash::advance();
     tss::load();
+    splash::advance();
     idt::load();
+    splash::advance();
     pic::disable();
+    splash::advance();
     io_apic::init();
+    splash::advance();
     local_apic::init();
+    splash::advance();
+    keyboard::init();
+    splash::advance();
 }
 
 pub fn init_ap(cpu_id: u32) {
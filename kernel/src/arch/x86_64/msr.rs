@@ -3,6 +3,7 @@ use core::arch::asm;
 #[repr(u32)]
 pub enum ModelSpecificRegister {
     ApicBase = 0x0000_001B,
+    Pat = 0x0000_0277,
     Efer = 0xC000_0080,
     Star = 0xC000_0081,
     LStar = 0xC000_0082,
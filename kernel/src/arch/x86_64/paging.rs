@@ -1,17 +1,24 @@
 use core::{
-    alloc::{GlobalAlloc, Layout},
     arch::asm,
-    ops::{Deref, Index, IndexMut},
+    ops::{Index, IndexMut, Range},
 };
 
 use bit_field::BitField;
 
-use crate::{memory::PAGE_ALLOCATOR, paging};
+use crate::{allocators::buddy_allocator::Frame, memory, paging};
+
+use super::{cpu, msr::ModelSpecificRegister, tlb::TlbShootdown};
 
 const TABLE_ENTRY_COUNT: usize = 512;
 
 pub const MIN_PAGE_SIZE: usize = 4096;
 
+/// End of the lower half of virtual addresses, which is where user pages are mapped
+pub const LOWER_HALF_END: usize = 0x0000_8000_0000_0000;
+
+/// Index of the first entry of a level 4 table that maps the higher half
+const HIGHER_HALF_INDEX: usize = TABLE_ENTRY_COUNT / 2;
+
 #[derive(Debug, Clone)]
 #[repr(C, align(4096))]
 pub struct PageTable {
@@ -26,152 +33,449 @@ impl PageTable {
     }
 
     pub fn translate(&self, virt: usize) -> Option<usize> {
-        let offset = *PageTableOffset::from(virt);
         let indices = PageTableIndices::from(virt);
 
         let mut table = self;
 
-        for (level, index) in [indices.p4_index, indices.p3_index, indices.p2_index]
-            .into_iter()
-            .enumerate()
-            .map(|(l, i)| (4 - l, i))
-        {
-            let entry = &table[index];
+        for level in (1..=4).rev() {
+            let entry = &table[indices.get(level)];
 
             if !entry.is_present() {
                 return None;
             }
 
-            if entry.is_huge() {
-                match level {
-                    2 => {
-                        return Some(
-                            entry.get_phys() as usize
-                                | ((indices.p1_index as usize) << 12)
-                                | offset as usize,
-                        );
-                    }
-
-                    3 => {
-                        return Some(
-                            entry.get_phys() as usize
-                                | ((indices.p2_index as usize) << 12 << 9)
-                                | ((indices.p1_index as usize) << 12)
-                                | offset as usize,
-                        );
-                    }
-
-                    _ => panic!("huge page bit must not be set in a level {} page", level),
+            // The huge page bit is the PAT bit in level 1 entries
+            if level == 1 || entry.is_huge() {
+                if level == 4 {
+                    panic!("huge page bit must not be set in a level {} page", level);
                 }
+
+                let page_size = get_page_size_of(level);
+
+                return Some(
+                    (entry.get_phys() as usize & !(page_size - 1)) | (virt & (page_size - 1)),
+                );
             }
 
             table = entry.get_page_table();
         }
 
-        let entry = &table[indices.p1_index];
+        unreachable!()
+    }
 
-        if entry.is_present() {
-            Some(entry.get_phys() as usize | offset as usize)
-        } else {
-            None
+    /// Creates every missing level 3 table of the higher half in this level 4 table, so its
+    /// higher half entries never change after this, and copies of them stay valid
+    pub fn populate_higher_half(&mut self) {
+        for entry in self.entries[HIGHER_HALF_INDEX..].iter_mut() {
+            if entry.is_present() {
+                continue;
+            }
+
+            let new_table = memory::alloc_frame().expect("out of memory for page tables");
+
+            unsafe {
+                (new_table.virt() as *mut PageTable).write(PageTable::empty());
+            }
+
+            entry.set_phys(new_table.phys() as u64);
+            entry.set_present(true);
+            entry.set_writable(true);
         }
     }
 
-    pub fn map(&mut self, virt: usize, phys: usize) -> &mut Entry {
-        debug_assert_eq!(virt % MIN_PAGE_SIZE, 0);
-        debug_assert_eq!(phys % MIN_PAGE_SIZE, 0);
-
-        let indices = PageTableIndices::from(virt);
+    /// Makes the higher half of this level 4 table point to the same tables as `other`, which
+    /// must be populated, so both share the kernel's mappings
+    pub fn share_higher_half_of(&mut self, other: &PageTable) {
+        self.entries[HIGHER_HALF_INDEX..].copy_from_slice(&other.entries[HIGHER_HALF_INDEX..]);
+    }
 
-        let mut table = self;
+    /// Copies the tables of the lower half of this level 4 table into `copy`, whose lower half
+    /// is empty, so the copy maps the same frames, `f` is called with the address, the
+    /// original and the copy of every entry that maps a page, the tables that were copied
+    /// before failing are kept in `copy`
+    pub fn try_copy_lower_half<F: FnMut(usize, &mut Entry, &mut Entry)>(
+        &mut self,
+        copy: &mut PageTable,
+        f: &mut F,
+    ) -> Result<(), MapError> {
+        self.try_copy_entries(copy, 4, 0, 0..HIGHER_HALF_INDEX, f)
+    }
 
-        for index in [indices.p4_index, indices.p3_index, indices.p2_index] {
-            let entry = &mut table[index];
+    fn try_copy_entries<F: FnMut(usize, &mut Entry, &mut Entry)>(
+        &mut self,
+        copy: &mut PageTable,
+        level: usize,
+        base: usize,
+        indices: Range<usize>,
+        f: &mut F,
+    ) -> Result<(), MapError> {
+        for index in indices {
+            let entry = &mut self.entries[index];
 
             if !entry.is_present() {
-                let new_table_virt = unsafe { PAGE_ALLOCATOR.alloc(Layout::new::<PageTable>()) };
+                continue;
+            }
 
-                unsafe {
-                    new_table_virt.cast::<PageTable>().write(PageTable::empty());
-                }
+            let virt = base + index * get_page_size_of(level);
 
-                let new_table_phys = get_active_table()
-                    .translate(new_table_virt as usize)
-                    .unwrap();
+            // The huge page bit is the PAT bit in level 1 entries
+            if level == 1 || entry.is_huge() {
+                let mut copied_entry = *entry;
 
-                entry.set_phys(new_table_phys as usize as u64);
-                entry.set_present(true);
-                entry.set_writable(true);
-            } else if entry.is_huge() {
-                todo!("mapping a huge page");
+                f(virt, entry, &mut copied_entry);
+
+                copy.entries[index] = copied_entry;
+
+                continue;
             }
 
-            table = entry.get_page_table();
+            let new_table = memory::alloc_frame().ok_or(MapError::OutOfMemory)?;
+
+            unsafe {
+                (new_table.virt() as *mut PageTable).write(PageTable::empty());
+            }
+
+            let copied_entry = &mut copy.entries[index];
+
+            *copied_entry = *entry;
+            copied_entry.set_phys(new_table.phys() as u64);
+
+            entry.get_page_table().try_copy_entries(
+                copied_entry.get_page_table(),
+                level - 1,
+                virt,
+                0..TABLE_ENTRY_COUNT,
+                f,
+            )?;
         }
 
-        let entry = &mut table[indices.p1_index];
+        Ok(())
+    }
+
+    /// Frees every table of the lower half of this level 4 table, but not the frames they map
+    pub fn free_lower_half(&mut self) {
+        for entry in self.entries[..HIGHER_HALF_INDEX].iter_mut() {
+            if entry.is_present() {
+                entry.free_page_table(3);
+            }
+
+            *entry = Entry(0);
+        }
+    }
+
+    /// Calls `f` with the physical address of every page table that is reachable from this
+    /// one, which is a level `level` table
+    pub fn for_each_table<F: FnMut(usize)>(&self, level: usize, f: &mut F) {
+        if level <= 1 {
+            return;
+        }
+
+        for entry in self.entries.iter() {
+            if entry.is_present() && !entry.is_huge() {
+                f(entry.get_phys() as usize);
+
+                entry.get_page_table().for_each_table(level - 1, f);
+            }
+        }
+    }
+
+    /// Maps the page at `virt` to the frame at `phys`, a page that was mapped there is added to
+    /// `shootdown`, which must be flushed by the CPUs that use this table
+    pub fn map(&mut self, virt: usize, phys: usize, shootdown: &mut TlbShootdown) -> &mut Entry {
+        self.try_map(virt, phys, shootdown)
+            .expect("out of memory for page tables")
+    }
+
+    /// Like `map`, but fails instead of panicking if a page table can not be allocated, the
+    /// tables that were allocated before failing are kept, as they are still valid
+    pub fn try_map(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        shootdown: &mut TlbShootdown,
+    ) -> Result<&mut Entry, MapError> {
+        self.try_map_at_level(virt, phys, 1, shootdown)
+    }
+
+    /// Maps a huge page, any smaller pages that were mapped in its range are replaced, and the
+    /// tables that held them are freed once `shootdown` is flushed
+    pub fn map_huge(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        size: HugePageSize,
+        shootdown: &mut TlbShootdown,
+    ) -> &mut Entry {
+        self.try_map_huge(virt, phys, size, shootdown)
+            .expect("out of memory for page tables")
+    }
+
+    /// Like `map_huge`, but fails instead of panicking if a page table can not be allocated
+    pub fn try_map_huge(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        size: HugePageSize,
+        shootdown: &mut TlbShootdown,
+    ) -> Result<&mut Entry, MapError> {
+        assert!(
+            size != HugePageSize::Size1GiB || cpu::supports_1gib_pages(),
+            "1 GiB pages are not supported by the CPU"
+        );
+
+        self.try_map_at_level(virt, phys, size.level(), shootdown)
+    }
+
+    fn try_map_at_level(
+        &mut self,
+        virt: usize,
+        phys: usize,
+        level: usize,
+        shootdown: &mut TlbShootdown,
+    ) -> Result<&mut Entry, MapError> {
+        debug_assert_eq!(virt % get_page_size_of(level), 0);
+        debug_assert_eq!(phys % get_page_size_of(level), 0);
 
-        let was_present = entry.is_present();
+        let entry = self.get_or_create_entry(virt, level, shootdown)?;
+        let old_entry = *entry;
 
         entry.set_phys(phys as u64);
         entry.set_present(true);
 
-        if was_present {
-            unsafe { asm!("invlpg [{}]", in(reg) virt, options(preserves_flags)) }
+        // The huge page bit is the PAT bit in level 1 entries
+        if level > 1 {
+            entry.set_huge(true);
+        }
+
+        if old_entry.is_present() {
+            let range = virt..virt + get_page_size_of(level);
+
+            if level > 1 && !old_entry.is_huge() {
+                // Smaller pages were mapped in the range of the huge page
+                shootdown.add_unlinked_table(range, old_entry, level - 1);
+            } else {
+                shootdown.add_range(range);
+            }
         }
 
-        entry
+        Ok(entry)
+    }
+
+    /// The level 1 entry that maps `virt`, or `None` if it is not mapped, a huge page that
+    /// contains it is split, which is added to `shootdown`
+    pub fn get_mapped(&mut self, virt: usize, shootdown: &mut TlbShootdown) -> Option<&mut Entry> {
+        self.get_entry(virt, 1, shootdown)
+            .expect("out of memory for splitting a huge page")
+            .filter(|entry| entry.is_present())
+    }
+
+    /// Unmaps a single page, a huge page that contains it is split, so only this page is
+    /// unmapped, the page is added to `shootdown`
+    pub fn unmap(&mut self, virt: usize, shootdown: &mut TlbShootdown) {
+        self.unmap_at_level(virt, 1, shootdown);
     }
 
-    pub fn unmap(&mut self, virt: usize) {
-        debug_assert_eq!(virt % MIN_PAGE_SIZE, 0);
+    /// Unmaps a huge page, or every smaller page in its range, the tables that held them are
+    /// freed once `shootdown` is flushed, a bigger huge page that contains it is split
+    pub fn unmap_huge(&mut self, virt: usize, size: HugePageSize, shootdown: &mut TlbShootdown) {
+        self.unmap_at_level(virt, size.level(), shootdown);
+    }
+
+    fn unmap_at_level(&mut self, virt: usize, level: usize, shootdown: &mut TlbShootdown) {
+        debug_assert_eq!(virt % get_page_size_of(level), 0);
+
+        let Some(entry) = self
+            .get_entry(virt, level, shootdown)
+            .expect("out of memory for splitting a huge page")
+        else {
+            return;
+        };
 
+        if !entry.is_present() {
+            return;
+        }
+
+        entry.set_present(false);
+
+        let range = virt..virt + get_page_size_of(level);
+
+        if level > 1 && !entry.is_huge() {
+            shootdown.add_unlinked_table(range, *entry, level - 1);
+        } else {
+            shootdown.add_range(range);
+        }
+    }
+
+    /// The entry that maps `virt` in the level `level` table, the tables above it are created,
+    /// and huge pages above it are split
+    fn get_or_create_entry(
+        &mut self,
+        virt: usize,
+        level: usize,
+        shootdown: &mut TlbShootdown,
+    ) -> Result<&mut Entry, MapError> {
         let indices = PageTableIndices::from(virt);
 
         let mut table = self;
 
-        for index in [indices.p4_index, indices.p3_index, indices.p2_index] {
-            let entry = &table[index];
+        for table_level in (level + 1..=4).rev() {
+            let entry = &mut table[indices.get(table_level)];
 
             if !entry.is_present() {
-                return;
+                let new_table = memory::alloc_frame().ok_or(MapError::OutOfMemory)?;
+
+                unsafe {
+                    (new_table.virt() as *mut PageTable).write(PageTable::empty());
+                }
+
+                entry.set_phys(new_table.phys() as u64);
+                entry.set_present(true);
+                entry.set_writable(true);
+
+                // Only the entry that maps the page decides whether user code can access it
+                entry.set_user_accessible(virt < LOWER_HALF_END);
+
+                // An unmapped huge page leaves the huge page bit behind
+                entry.set_huge(false);
+            } else if entry.is_huge() {
+                entry.split(table_level)?;
+
+                shootdown.add(virt);
+            }
+
+            table = entry.get_page_table();
+        }
+
+        Ok(&mut table[indices.get(level)])
+    }
+
+    /// The entry that maps `virt` in the level `level` table, or `None` if a table above it is
+    /// missing, huge pages above it are split
+    fn get_entry(
+        &mut self,
+        virt: usize,
+        level: usize,
+        shootdown: &mut TlbShootdown,
+    ) -> Result<Option<&mut Entry>, MapError> {
+        let indices = PageTableIndices::from(virt);
+
+        let mut table = self;
+
+        for table_level in (level + 1..=4).rev() {
+            let entry = &mut table[indices.get(table_level)];
+
+            if !entry.is_present() {
+                return Ok(None);
             }
 
             if entry.is_huge() {
-                todo!("unmapping a huge page");
+                entry.split(table_level)?;
+
+                shootdown.add(virt);
             }
 
             table = entry.get_page_table();
         }
 
-        let entry = &mut table[indices.p1_index];
+        Ok(Some(&mut table[indices.get(level)]))
+    }
+}
 
-        let was_present = entry.is_present();
+/// The size of the memory that an entry of a level `level` table maps
+#[inline(always)]
+const fn get_page_size_of(level: usize) -> usize {
+    MIN_PAGE_SIZE << (9 * (level - 1))
+}
 
-        entry.set_present(false);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePageSize {
+    /// Mapped by a level 2 entry
+    Size2MiB,
+    /// Mapped by a level 3 entry, which needs support from the CPU
+    Size1GiB,
+}
 
-        if was_present {
-            unsafe { asm!("invlpg [{}]", in(reg) virt, options(preserves_flags)) }
+impl HugePageSize {
+    #[inline(always)]
+    const fn level(self) -> usize {
+        match self {
+            HugePageSize::Size2MiB => 2,
+            HugePageSize::Size1GiB => 3,
         }
     }
+
+    #[inline(always)]
+    pub const fn bytes(self) -> usize {
+        get_page_size_of(self.level())
+    }
 }
 
-#[derive(Debug)]
-#[repr(transparent)]
-struct PageTableOffset(u16);
+#[inline(always)]
+pub fn flush(virt: usize) {
+    unsafe { asm!("invlpg [{}]", in(reg) virt, options(preserves_flags)) }
+}
 
-impl From<usize> for PageTableOffset {
-    fn from(virt: usize) -> Self {
-        Self((virt % (1 << 12)) as u16)
+/// Flushes every translation that is not global, by reloading CR3
+#[inline(always)]
+pub fn flush_all() {
+    unsafe {
+        asm!("mov {0}, cr3", "mov cr3, {0}", out(reg) _, options(preserves_flags));
     }
 }
 
-impl Deref for PageTableOffset {
-    type Target = u16;
+/// The address that the last page fault accessed, which is kept in CR2 until the next one
+#[inline(always)]
+pub fn get_fault_address() -> usize {
+    let address: usize;
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    unsafe {
+        asm!("mov {}, cr2", out(reg) address, options(nomem, nostack, preserves_flags));
     }
+
+    address
+}
+
+/// A page fault, as the CPU reported it
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    /// The address that was accessed
+    pub address: usize,
+    /// Whether the page was mapped, so the fault is because the access was not allowed
+    pub was_present: bool,
+    pub is_write: bool,
+    /// Whether the access came from user code
+    pub is_user: bool,
+    pub is_instruction_fetch: bool,
+}
+
+impl PageFault {
+    /// Decodes the error code of a page fault, the accessed address is read from CR2, so this
+    /// must be called before anything else can fault
+    pub fn from_code(code: u64) -> PageFault {
+        PageFault {
+            address: get_fault_address(),
+            was_present: code.get_bit(0),
+            is_write: code.get_bit(1),
+            is_user: code.get_bit(2),
+            is_instruction_fetch: code.get_bit(4),
+        }
+    }
+
+    pub fn access_as_str(&self) -> &'static str {
+        if self.is_instruction_fetch {
+            "execute"
+        } else if self.is_write {
+            "write"
+        } else {
+            "read"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapError {
+    /// There is no free frame for a page table that is needed for the mapping
+    OutOfMemory,
 }
 
 #[derive(Debug)]
@@ -182,6 +486,20 @@ struct PageTableIndices {
     p4_index: u16,
 }
 
+impl PageTableIndices {
+    /// The index into the level `level` table
+    #[inline(always)]
+    fn get(&self, level: usize) -> u16 {
+        match level {
+            1 => self.p1_index,
+            2 => self.p2_index,
+            3 => self.p3_index,
+            4 => self.p4_index,
+            _ => unreachable!(),
+        }
+    }
+}
+
 impl From<usize> for PageTableIndices {
     fn from(virt: usize) -> Self {
         Self {
@@ -207,6 +525,50 @@ impl IndexMut<u16> for PageTable {
     }
 }
 
+/// The PAT bit of level 1 entries, which is the huge page bit in the other levels
+const PAT_BIT: usize = 7;
+
+/// The PAT bit of entries that map huge pages
+const HUGE_PAT_BIT: usize = 12;
+
+/// One of the bits that the CPU leaves for us
+const COPY_ON_WRITE_BIT: usize = 9;
+
+/// How accesses to memory are cached, which is chosen for each page through an entry of the PAT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    /// Accesses go to memory in order, for the registers of devices
+    Uncacheable,
+    /// Writes are combined in a buffer before going to memory, for framebuffers
+    WriteCombining,
+    /// Reads are cached, writes go to memory right away
+    WriteThrough,
+    /// Reads and writes are cached, for normal memory
+    WriteBack,
+}
+
+impl CacheType {
+    /// The entry of the PAT that has this memory type, as `init_pat` sets up the PAT
+    #[inline(always)]
+    const fn get_pat_index(self) -> u64 {
+        match self {
+            CacheType::WriteBack => 0,
+            CacheType::WriteThrough => 1,
+            CacheType::Uncacheable => 3,
+            CacheType::WriteCombining => 5,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheType::Uncacheable => "uc",
+            CacheType::WriteCombining => "wc",
+            CacheType::WriteThrough => "wt",
+            CacheType::WriteBack => "wb",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(transparent)]
 pub struct Entry(u64);
@@ -225,6 +587,62 @@ impl Entry {
         self
     }
 
+    /// Turns a huge page into a table of pages of the next smaller size, which map the same
+    /// frames with the same flags, `level` is the level of the table this entry is in
+    fn split(&mut self, level: usize) -> Result<(), MapError> {
+        let page_size = get_page_size_of(level);
+        let smaller_page_size = get_page_size_of(level - 1);
+
+        // Bit 12 of huge entries is the PAT bit, which is not part of the address
+        let phys = self.get_phys() as usize & !(page_size - 1);
+
+        let new_table = memory::alloc_frame().ok_or(MapError::OutOfMemory)?;
+        let table = unsafe { &mut *(new_table.virt() as *mut PageTable) };
+
+        let is_pat_set = self.0.get_bit(HUGE_PAT_BIT);
+        let mut smaller_entry = *self;
+
+        // The huge page bit is the PAT bit in level 1 entries
+        smaller_entry.set_huge(level - 1 > 1);
+
+        if level - 1 == 1 {
+            smaller_entry.0.set_bit(PAT_BIT, is_pat_set);
+        }
+
+        for (i, entry) in table.entries.iter_mut().enumerate() {
+            *entry = smaller_entry;
+            entry.set_phys((phys + i * smaller_page_size) as u64);
+
+            if level - 1 > 1 {
+                entry.0.set_bit(HUGE_PAT_BIT, is_pat_set);
+            }
+        }
+
+        // The memory type of the huge page has nothing to do with the memory type of the table
+        self.set_huge(false);
+        self.set_phys(new_table.phys() as u64);
+        self.set_write_through(false);
+        self.set_cachability(true);
+
+        Ok(())
+    }
+
+    /// Frees the level `level` page table that this entry is pointing to, along with every
+    /// table below it, but not the frames they map
+    pub(super) fn free_page_table(&self, level: usize) {
+        let table = self.get_page_table();
+
+        if level > 1 {
+            for entry in table.entries.iter() {
+                if entry.is_present() && !entry.is_huge() {
+                    entry.free_page_table(level - 1);
+                }
+            }
+        }
+
+        memory::free_frame(Frame::from_phys(self.get_phys() as usize));
+    }
+
     /// Get the page table that this entry is pointing to
     #[inline]
     pub fn get_page_table(&self) -> &'static mut PageTable {
@@ -255,6 +673,12 @@ impl Entry {
         self
     }
 
+    /// Whether writes to the mapped frames are allowed.
+    #[inline]
+    pub fn is_writable(&self) -> bool {
+        self.0.get_bit(1)
+    }
+
     /// Controls whether accesses from userspace (i.e. ring 3) are permitted.
     #[inline]
     pub fn set_user_accessible(&mut self, is_user_accessible: bool) -> &mut Self {
@@ -278,6 +702,30 @@ impl Entry {
         self
     }
 
+    /// Sets the memory type of the mapped frame, only for entries of level 1 tables, as the PAT
+    /// bit is somewhere else in huge pages
+    #[inline]
+    pub fn set_cache_type(&mut self, cache_type: CacheType) -> &mut Self {
+        let pat_index = cache_type.get_pat_index();
+
+        self.0.set_bit(3, pat_index.get_bit(0));
+        self.0.set_bit(4, pat_index.get_bit(1));
+        self.0.set_bit(PAT_BIT, pat_index.get_bit(2));
+        self
+    }
+
+    /// Sets the memory type of the mapped huge page, only for entries that map 2 MiB or 1 GiB
+    /// pages
+    #[inline]
+    pub fn set_huge_cache_type(&mut self, cache_type: CacheType) -> &mut Self {
+        let pat_index = cache_type.get_pat_index();
+
+        self.0.set_bit(3, pat_index.get_bit(0));
+        self.0.set_bit(4, pat_index.get_bit(1));
+        self.0.set_bit(HUGE_PAT_BIT, pat_index.get_bit(2));
+        self
+    }
+
     /// Set by the CPU when the mapped frame or page table is accessed.
     #[inline]
     pub fn was_accessed(&self) -> bool {
@@ -323,6 +771,64 @@ impl Entry {
         self.0.set_bit(63, !is_executable);
         self
     }
+
+    /// Whether code execution from the mapped frames is allowed.
+    #[inline]
+    pub fn is_executable(&self) -> bool {
+        !self.0.get_bit(63)
+    }
+
+    /// Whether the mapped frame may be shared with other address spaces, so it is read only,
+    /// and copied on the first write to it.
+    #[inline]
+    pub fn is_copy_on_write(&self) -> bool {
+        self.0.get_bit(COPY_ON_WRITE_BIT)
+    }
+
+    /// Marks the mapped frame as shared with other address spaces, this bit is ignored by the
+    /// CPU, so the entry must be made read only too.
+    #[inline]
+    pub fn set_copy_on_write(&mut self, is_copy_on_write: bool) -> &mut Self {
+        self.0.set_bit(COPY_ON_WRITE_BIT, is_copy_on_write);
+        self
+    }
+}
+
+/// Lets entries mark pages as not executable, otherwise `Entry::set_executability` has no
+/// effect, this must be done on every CPU
+pub fn enable_no_execute() {
+    assert!(
+        cpu::supports_no_execute(),
+        "the CPU does not support no-execute pages"
+    );
+
+    ModelSpecificRegister::Efer.write(ModelSpecificRegister::Efer.read() | (1 << 11));
+}
+
+/// The memory type of each entry of the PAT, which is what the CPU starts with, except that
+/// entry 4 is write protected and entry 5 is write combining
+const PAT: [u8; 8] = [
+    0x06, // Write back
+    0x04, // Write through
+    0x07, // Uncached, which the MTRRs can turn into write combining
+    0x00, // Uncacheable
+    0x05, // Write protected
+    0x01, // Write combining
+    0x07, // Uncached, which the MTRRs can turn into write combining
+    0x00, // Uncacheable
+];
+
+/// Programs the PAT so every `CacheType` has an entry of its own, this must be done on every
+/// CPU, as entries that use different memory types for the same memory would conflict otherwise
+pub fn init_pat() {
+    assert!(cpu::supports_pat(), "the CPU does not support the PAT");
+
+    ModelSpecificRegister::Pat.write(u64::from_le_bytes(PAT));
+
+    // Nothing may stay cached or translated with the memory type of the old PAT
+    unsafe { asm!("wbinvd", options(nostack, preserves_flags)) };
+
+    flush_all();
 }
 
 #[inline]
@@ -0,0 +1,38 @@
+use core::ops::Range;
+
+// These are defined by the linker script
+unsafe extern "C" {
+    static __kernel_start: u8;
+    static __kernel_end: u8;
+    static __text_start: u8;
+    static __text_end: u8;
+    static __rodata_start: u8;
+    static __rodata_end: u8;
+    static __data_start: u8;
+    static __data_end: u8;
+}
+
+#[inline(always)]
+fn get_range(start: &u8, end: &u8) -> Range<usize> {
+    (start as *const u8).addr()..(end as *const u8).addr()
+}
+
+/// Virtual addresses of the whole kernel image
+pub fn kernel() -> Range<usize> {
+    unsafe { get_range(&__kernel_start, &__kernel_end) }
+}
+
+/// Virtual addresses of the executable code
+pub fn text() -> Range<usize> {
+    unsafe { get_range(&__text_start, &__text_end) }
+}
+
+/// Virtual addresses of the read only data
+pub fn rodata() -> Range<usize> {
+    unsafe { get_range(&__rodata_start, &__rodata_end) }
+}
+
+/// Virtual addresses of the writable data, including the zero initialized data
+pub fn data() -> Range<usize> {
+    unsafe { get_range(&__data_start, &__data_end) }
+}
@@ -0,0 +1,71 @@
+use core::fmt::{self, Write};
+
+use spin::Mutex;
+
+use super::io_ports::{inb, outb};
+
+pub const COM1_PORT: u16 = 0x3F8;
+
+pub static SERIAL: Mutex<SerialPort> = Mutex::new(SerialPort::new(COM1_PORT));
+
+#[derive(Debug, Clone, Copy)]
+pub struct SerialPort(u16);
+
+impl SerialPort {
+    pub const fn new(port: u16) -> SerialPort {
+        SerialPort(port)
+    }
+
+    pub fn init(&self) {
+        // Disable all interrupts
+        outb(self.0 + 1, 0x00);
+        // Enable DLAB to set the baud rate divisor
+        outb(self.0 + 3, 0x80);
+        // Set the divisor to 1 (115200 baud)
+        outb(self.0, 0x01);
+        outb(self.0 + 1, 0x00);
+        // 8 bits, no parity, one stop bit
+        outb(self.0 + 3, 0x03);
+        // Enable and clear the FIFOs, with a 14-byte threshold
+        outb(self.0 + 2, 0xC7);
+        // Set RTS and DTR
+        outb(self.0 + 4, 0x03);
+    }
+
+    #[inline(always)]
+    fn is_transmit_empty(&self) -> bool {
+        inb(self.0 + 5) & 0x20 != 0
+    }
+
+    pub fn write_byte(&self, byte: u8) {
+        while !self.is_transmit_empty() {
+            core::hint::spin_loop();
+        }
+
+        outb(self.0, byte);
+    }
+
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_byte(byte);
+        }
+    }
+}
+
+impl Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
+
+pub fn init() {
+    SERIAL.lock().init();
+}
@@ -0,0 +1,220 @@
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+
+use crate::{
+    mp::MAX_CPU_COUNT,
+    paging::{self, Entry, MIN_PAGE_SIZE},
+};
+
+use super::{cpu, local_apic::LocalApic};
+
+/// The interrupt that asks a CPU to flush the translations of a shootdown
+pub const SHOOTDOWN_VECTOR: u8 = 33;
+
+/// Flushing more pages than this one by one is slower than flushing every translation
+const MAX_FLUSHED_PAGE_COUNT: usize = 32;
+
+/// A shootdown is flushed early when more page tables than this were unlinked before it
+const MAX_UNLINKED_TABLE_COUNT: usize = 4;
+
+/// Sets of CPUs are bitmasks with a bit for each CPU id
+const _: () = assert!(MAX_CPU_COUNT <= u64::BITS as usize);
+
+/// Every CPU, for mappings of the kernel, which every CPU uses
+pub const ALL_CPUS: u64 = u64::MAX;
+
+/// The CPUs that take part in shootdowns, which are the ones that handle interrupts
+static ONLINE_CPUS: AtomicU64 = AtomicU64::new(0);
+
+/// Only one shootdown is sent at a time, the others wait for it to be acknowledged
+static IS_SHOOTDOWN_SENT: AtomicBool = AtomicBool::new(false);
+
+/// The range of the shootdown that was sent, an empty range flushes every translation
+static SHOOTDOWN_START: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWN_END: AtomicUsize = AtomicUsize::new(0);
+
+/// The CPUs that did not flush the range of the shootdown that was sent yet
+static PENDING_CPUS: AtomicU64 = AtomicU64::new(0);
+
+/// Makes the CPU we are running on take part in shootdowns, it must handle interrupts soon
+/// after this, as other CPUs wait for it
+pub fn init() {
+    let cpu_id = cpu::get_current_id().expect("the cpu is not initialized");
+
+    ONLINE_CPUS.fetch_or(1 << cpu_id, Ordering::SeqCst);
+}
+
+/// Stops the CPU we are running on from taking part in shootdowns, which is done before it halts
+/// for good, so other CPUs do not wait for it forever
+pub fn leave() {
+    let current_cpu_bit = get_current_cpu_bit();
+
+    ONLINE_CPUS.fetch_and(!current_cpu_bit, Ordering::SeqCst);
+    PENDING_CPUS.fetch_and(!current_cpu_bit, Ordering::Release);
+}
+
+/// The bit of the CPU we are running on in sets of CPUs, or zero if it is not initialized yet,
+/// then it is the only CPU that runs
+#[inline(always)]
+pub fn get_current_cpu_bit() -> u64 {
+    cpu::get_current_id().map_or(0, |cpu_id| 1 << cpu_id)
+}
+
+/// The pages whose translations are stale on a set of CPUs, which are flushed on all of them
+/// at once when the shootdown is flushed or dropped
+pub struct TlbShootdown {
+    cpus: u64,
+    range: Option<Range<usize>>,
+    is_flushing_everything: bool,
+    /// Page tables that were unlinked, with their levels, other CPUs may still walk them until
+    /// they flush, so they are freed after that
+    unlinked_tables: [Option<(Entry, usize)>; MAX_UNLINKED_TABLE_COUNT],
+}
+
+impl TlbShootdown {
+    /// A shootdown for the CPUs in `cpus`, the CPU we are running on always flushes
+    pub const fn new(cpus: u64) -> TlbShootdown {
+        TlbShootdown {
+            cpus,
+            range: None,
+            is_flushing_everything: false,
+            unlinked_tables: [None; MAX_UNLINKED_TABLE_COUNT],
+        }
+    }
+
+    /// Adds the page at `virt`, the range of a shootdown covers every page that was added
+    pub fn add(&mut self, virt: usize) {
+        self.add_range(virt..virt + MIN_PAGE_SIZE);
+    }
+
+    pub fn add_range(&mut self, range: Range<usize>) {
+        self.range = Some(match self.range.take() {
+            Some(current) => current.start.min(range.start)..current.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// Makes the shootdown flush every translation
+    pub fn add_everything(&mut self) {
+        self.is_flushing_everything = true;
+    }
+
+    /// Adds the pages in `range`, which were mapped by the level `level` page table that `table`
+    /// points to, the table is freed once every CPU of the shootdown flushed them
+    pub fn add_unlinked_table(&mut self, range: Range<usize>, table: Entry, level: usize) {
+        if self.unlinked_tables.iter().all(Option::is_some) {
+            self.flush();
+        }
+
+        self.add_range(range);
+
+        let slot = self
+            .unlinked_tables
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .unwrap();
+
+        *slot = Some((table, level));
+    }
+
+    /// Flushes the pages on every CPU of the shootdown, and waits for them to be flushed, then
+    /// frees the page tables that were unlinked
+    pub fn flush(&mut self) {
+        self.flush_translations();
+
+        for (table, level) in self.unlinked_tables.iter_mut().filter_map(Option::take) {
+            table.free_page_table(level);
+        }
+    }
+
+    fn flush_translations(&mut self) {
+        let range = if self.is_flushing_everything {
+            0..0
+        } else {
+            match self.range.take() {
+                Some(range) if range.len() / MIN_PAGE_SIZE <= MAX_FLUSHED_PAGE_COUNT => range,
+                Some(_) => 0..0,
+                None => return,
+            }
+        };
+
+        self.range = None;
+        self.is_flushing_everything = false;
+
+        flush_range(range.clone());
+
+        // A CPU that is not initialized yet has no local APIC to send interrupts with, and it
+        // only unmaps what it mapped itself, which no other CPU used yet
+        let Some(cpu_id) = cpu::get_current_id() else {
+            return;
+        };
+
+        let targets = self.cpus & ONLINE_CPUS.load(Ordering::SeqCst) & !(1 << cpu_id);
+
+        if targets == 0 {
+            return;
+        }
+
+        // Another CPU may be waiting for us to flush while we wait for it to finish
+        while IS_SHOOTDOWN_SENT
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            handle_shootdown();
+
+            core::hint::spin_loop();
+        }
+
+        SHOOTDOWN_START.store(range.start, Ordering::Relaxed);
+        SHOOTDOWN_END.store(range.end, Ordering::Relaxed);
+
+        PENDING_CPUS.store(targets, Ordering::Release);
+
+        let local_apic = LocalApic::get();
+
+        for cpu_id in (0..MAX_CPU_COUNT).filter(|cpu_id| targets & (1 << cpu_id) != 0) {
+            let target = LocalApic::get_of(cpu_id).expect("an online cpu has no local apic");
+
+            local_apic.send_ipi(target.id(), SHOOTDOWN_VECTOR);
+        }
+
+        // A CPU that left while we were waiting never flushes
+        while PENDING_CPUS.load(Ordering::Acquire) & ONLINE_CPUS.load(Ordering::SeqCst) != 0 {
+            core::hint::spin_loop();
+        }
+
+        IS_SHOOTDOWN_SENT.store(false, Ordering::Release);
+    }
+}
+
+impl Drop for TlbShootdown {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Flushes the range of the shootdown that was sent, if the CPU we are running on did not yet,
+/// called when the shootdown interrupt is received
+pub fn handle_shootdown() {
+    let current_cpu_bit = get_current_cpu_bit();
+
+    if PENDING_CPUS.load(Ordering::Acquire) & current_cpu_bit == 0 {
+        return;
+    }
+
+    flush_range(SHOOTDOWN_START.load(Ordering::Relaxed)..SHOOTDOWN_END.load(Ordering::Relaxed));
+
+    PENDING_CPUS.fetch_and(!current_cpu_bit, Ordering::Release);
+}
+
+/// Flushes the pages in `range` on the CPU we are running on, an empty range flushes every
+/// translation
+fn flush_range(range: Range<usize>) {
+    if range.is_empty() {
+        paging::flush_all();
+    } else {
+        range.step_by(MIN_PAGE_SIZE).for_each(paging::flush);
+    }
+}
@@ -1,11 +1,16 @@
-use core::arch::asm;
+use core::{arch::asm, cell::UnsafeCell};
 
-use lazy_static::lazy_static;
-
-use crate::mp::MAX_CPU_COUNT;
+use crate::{memory, mp::MAX_CPU_COUNT};
 
 use super::Cpu;
 
+/// The interrupt stack table entry of double faults, they need a stack of their own, as a
+/// stack overflow becomes a double fault that can not be handled on the overflowed stack
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+/// Size of the stack that double faults are handled on, which is enough for the panic handler
+const DOUBLE_FAULT_STACK_SIZE: usize = 64 * 1024;
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed(4))]
 pub struct TaskStateSegment {
@@ -38,26 +43,34 @@ impl Default for TaskStateSegment {
     }
 }
 
-lazy_static! {
-    pub static ref TSS: [TaskStateSegment; MAX_CPU_COUNT] = {
-        let mut tss = [const { TaskStateSegment::new() }; MAX_CPU_COUNT];
+/// The task state segment of each CPU, which is read by the CPU itself, each CPU only changes
+/// its own one before loading it
+pub struct TaskStateSegments([UnsafeCell<TaskStateSegment>; MAX_CPU_COUNT]);
 
-        for tss in tss.iter_mut() {
-            tss.interrupt_stack_table[0] = {
-                const IST_STACK_SIZE: usize = 20 * 1024;
-                static mut IST_STACK: [u8; IST_STACK_SIZE] = [0; IST_STACK_SIZE];
-                ((&raw const IST_STACK).addr() + IST_STACK_SIZE) as u64
-            };
-        }
+unsafe impl Sync for TaskStateSegments {}
 
-        tss
-    };
+impl TaskStateSegments {
+    #[inline(always)]
+    pub fn get(&self, cpu_id: usize) -> *mut TaskStateSegment {
+        self.0[cpu_id].get()
+    }
 }
 
+pub static TSS: TaskStateSegments =
+    TaskStateSegments([const { UnsafeCell::new(TaskStateSegment::new()) }; MAX_CPU_COUNT]);
+
+/// Gives the CPU we are running on its interrupt stacks, and loads its task state segment, the
+/// VMM must be initialized
 pub fn load() {
     let cpu = Cpu::get();
 
+    let double_fault_stack_end = memory::alloc_stack(DOUBLE_FAULT_STACK_SIZE, "double fault stack")
+        .expect("out of memory for the double fault stack");
+
     unsafe {
+        (*TSS.get(cpu.id as usize)).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] =
+            double_fault_stack_end as u64;
+
         asm!("ltr {0:x}", in(reg) (0x28 + (cpu.id * 16)), options(readonly, nostack, preserves_flags));
     }
 }
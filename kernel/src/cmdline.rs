@@ -0,0 +1,32 @@
+use alloc::string::String;
+
+use spin::Lazy;
+
+use crate::requests::EXECUTABLE_CMDLINE_REQUEST;
+
+/// A copy of the command line, since limine's response lives in memory that is reclaimed
+static CMDLINE: Lazy<String> = Lazy::new(|| {
+    EXECUTABLE_CMDLINE_REQUEST
+        .get_response()
+        .and_then(|response| response.cmdline().to_str().ok())
+        .map(String::from)
+        .unwrap_or_default()
+});
+
+/// The command line that the kernel was booted with, or an empty string if there is none
+pub fn get() -> &'static str {
+    &CMDLINE
+}
+
+/// Whether `flag` was passed on the command line on its own, e.g. `nosplash`
+pub fn has_flag(flag: &str) -> bool {
+    get().split_whitespace().any(|arg| arg == flag)
+}
+
+/// The value of the first `key=value` argument on the command line
+pub fn get_value(key: &str) -> Option<&'static str> {
+    get().split_whitespace().find_map(|arg| {
+        arg.split_once('=')
+            .and_then(|(arg_key, value)| (arg_key == key).then_some(value))
+    })
+}
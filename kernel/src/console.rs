@@ -1,13 +1,117 @@
-use core::fmt::{self, Write};
+use alloc::{collections::TryReserveError, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use lazy_static::lazy_static;
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 
 use crate::{
+    arch::serial::SERIAL,
+    graphics,
     psf2::Psf2Font,
     screen::{self, Color, FRAMEBUFFER},
 };
 
+/// Amount of lines kept after they scroll off the top of the screen
+pub const DEFAULT_SCROLLBACK_LINES: usize = 1000;
+
+/// Amount of virtual terminals, one for each of Alt+F1..F12
+pub const TERMINAL_COUNT: usize = 12;
+
+/// The virtual terminal that receives everything printed by the kernel
+pub const KERNEL_LOG_TERMINAL: usize = 0;
+
+/// Amount of lines kept by the terminals that are not the kernel log terminal
+pub const TERMINAL_SCROLLBACK_LINES: usize = 200;
+
+/// A single character on the console along with the colors it was written with
+#[derive(Clone, Copy)]
+pub struct Cell {
+    pub ch: char,
+    pub foreground: Color,
+    pub background: Color,
+}
+
+impl Cell {
+    pub const fn blank(background: Color) -> Cell {
+        Cell {
+            ch: ' ',
+            foreground: background,
+            background,
+        }
+    }
+}
+
+/// A ring buffer of lines of cells, the oldest line is dropped when pushing into a full buffer
+pub struct Scrollback {
+    cells: Vec<Cell>,
+    columns: usize,
+    capacity: usize,
+    /// Index of the oldest line in the ring
+    start: usize,
+    len: usize,
+}
+
+impl Scrollback {
+    pub fn try_new(columns: usize, capacity: usize) -> Result<Scrollback, TryReserveError> {
+        let mut cells = Vec::new();
+
+        cells.try_reserve_exact(columns * capacity)?;
+        cells.resize(columns * capacity, Cell::blank(Color::BLACK));
+
+        Ok(Scrollback {
+            cells,
+            columns,
+            capacity,
+            start: 0,
+            len: 0,
+        })
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline(always)]
+    fn get_line_range(&self, index: usize) -> core::ops::Range<usize> {
+        debug_assert!(index < self.len);
+
+        let start = ((self.start + index) % self.capacity) * self.columns;
+
+        start..start + self.columns
+    }
+
+    /// Get a line by its index, where 0 is the oldest line
+    pub fn line(&self, index: usize) -> &[Cell] {
+        &self.cells[self.get_line_range(index)]
+    }
+
+    /// Get a line by its index, where 0 is the oldest line
+    pub fn line_mut(&mut self, index: usize) -> &mut [Cell] {
+        let range = self.get_line_range(index);
+        &mut self.cells[range]
+    }
+
+    /// Appends a blank line, overwriting the oldest line if the buffer is full
+    pub fn push_line(&mut self, background: Color) {
+        if self.len < self.capacity {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % self.capacity;
+        }
+
+        self.line_mut(self.len - 1).fill(Cell::blank(background));
+    }
+}
+
 pub struct Console<'a> {
     pub font: Psf2Font<'a>,
     pub background: Color,
@@ -18,63 +122,180 @@ pub struct Console<'a> {
     pub y: usize,
     pub padding_x: usize,
     pub padding_y: usize,
+    /// Every line that was written, the last `self.rows()` lines are the ones at the bottom
+    pub scrollback: Scrollback,
+    /// How many lines the view is scrolled back from the bottom
+    pub scroll_offset: usize,
+    /// Whether this console owns the framebuffer, inactive consoles only update their scrollback
+    pub is_active: bool,
 }
 
 impl Default for Console<'_> {
     fn default() -> Self {
+        Console::new(DEFAULT_SCROLLBACK_LINES)
+    }
+}
+
+impl Console<'_> {
+    pub fn new(scrollback_lines: usize) -> Self {
         let font = Psf2Font::parse(include_bytes!("fonts/default8x16.psfu"));
         let padding_x = 2;
         let padding_y = 1;
 
-        Console {
+        let width = (FRAMEBUFFER.width() as usize / font.header.glyph_width as usize) - padding_x;
+        let height =
+            (FRAMEBUFFER.height() as usize / font.header.glyph_height as usize) - padding_y;
+
+        let rows = height - padding_y;
+
+        let mut console = Console {
             font,
             background: Color::BLACK,
             foreground: Color::WHITE,
-            width: (FRAMEBUFFER.width() as usize / font.header.glyph_width as usize) - padding_x,
-            height: (FRAMEBUFFER.height() as usize / font.header.glyph_height as usize) - padding_y,
+            width,
+            height,
             x: padding_x,
             y: padding_y,
             padding_x,
             padding_y,
-        }
+            // A console is also created by the panic handler, which may be running because we
+            // are out of memory, so we give up on the scrollback rather than on the screen
+            scrollback: Scrollback::try_new(width - padding_x, scrollback_lines + rows)
+                .or_else(|_| Scrollback::try_new(width - padding_x, rows))
+                .expect("out of memory for the lines on the screen"),
+            scroll_offset: 0,
+            is_active: true,
+        };
+
+        (0..console.rows()).for_each(|_| console.scrollback.push_line(console.background));
+
+        console
+    }
+
+    /// Amount of text rows visible on the screen
+    #[inline(always)]
+    pub fn rows(&self) -> usize {
+        self.height - self.padding_y
+    }
+
+    /// Amount of text columns visible on the screen
+    #[inline(always)]
+    pub fn columns(&self) -> usize {
+        self.width - self.padding_x
     }
-}
 
-impl Console<'_> {
     pub fn clear(&mut self) {
-        screen::get_colors().fill(self.background);
+        if self.is_active {
+            screen::get_colors().fill(self.background);
+        }
 
+        // Keep what was on the screen in the scrollback, and start over with blank lines
+        (0..self.rows()).for_each(|_| self.scrollback.push_line(self.background));
+
+        self.scroll_offset = 0;
         self.x = self.padding_x;
         self.y = self.padding_y;
     }
 
-    fn write_glyph(&self, glyph_bytes: &[u8]) {
-        let x = self.x * self.font.header.glyph_width as usize;
-        let y = self.y * self.font.header.glyph_height as usize;
+    /// Gives or takes the framebuffer from this console, the screen is redrawn when activated
+    pub fn set_active(&mut self, is_active: bool) {
+        self.is_active = is_active;
 
-        for dx in 0..self.font.header.glyph_width as usize {
-            for dy in 0..self.font.header.glyph_height as usize {
-                let font_bit = self.get_glyph_bit(
-                    glyph_bytes,
-                    self.font.header.glyph_width as usize - 1 - dx,
-                    dy,
-                );
+        if is_active {
+            screen::get_colors().fill(self.background);
 
-                if font_bit {
-                    *screen::get_color(x + dx, y + dy) = self.foreground;
-                } else {
-                    *screen::get_color(x + dx, y + dy) = self.background;
-                }
+            self.redraw();
+        }
+    }
+
+    /// Scrolls the view back by `lines`, meant to be bound to Shift+PageUp
+    pub fn scroll_up(&mut self, lines: usize) {
+        let max_offset = self.scrollback.len() - self.rows();
+
+        self.scroll_offset = (self.scroll_offset + lines).min(max_offset);
+
+        self.redraw();
+    }
+
+    /// Scrolls the view forward by `lines`, meant to be bound to Shift+PageDown
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll_offset = self.scroll_offset.saturating_sub(lines);
+
+        self.redraw();
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.rows());
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.rows());
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_offset = 0;
+
+        self.redraw();
+    }
+
+    /// Draws the visible lines of the scrollback again
+    pub fn redraw(&self) {
+        if !self.is_active {
+            return;
+        }
+
+        let first_line = self.scrollback.len() - self.rows() - self.scroll_offset;
+
+        for row in 0..self.rows() {
+            let line = self.scrollback.line(first_line + row);
+
+            for (column, &cell) in line.iter().enumerate() {
+                self.draw_cell(column + self.padding_x, row + self.padding_y, cell);
             }
         }
     }
 
-    fn get_glyph_bytes(&self, index: usize) -> &[u8] {
-        &self.font.data[index..index + self.font.header.glyph_height as usize]
+    /// Writes every line in the scrollback as text, trailing blanks are trimmed
+    pub fn dump_scrollback<W: Write>(&self, writer: &mut W) -> fmt::Result {
+        for index in 0..self.scrollback.len() {
+            let line = self.scrollback.line(index);
+
+            let line_len = line
+                .iter()
+                .rposition(|cell| cell.ch != ' ')
+                .map_or(0, |i| i + 1);
+
+            for cell in &line[..line_len] {
+                writer.write_char(cell.ch)?;
+            }
+
+            writer.write_char('\n')?;
+        }
+
+        Ok(())
     }
 
-    fn get_glyph_bit(&self, glyph_bytes: &[u8], x: usize, y: usize) -> bool {
-        (glyph_bytes[y] & (1 << x)) != 0
+    fn draw_cell(&self, x: usize, y: usize, cell: Cell) {
+        if !self.is_active {
+            return;
+        }
+
+        graphics::get_screen_canvas().draw_char(
+            (x * self.font.header.glyph_width as usize) as isize,
+            (y * self.font.header.glyph_height as usize) as isize,
+            cell.ch,
+            &self.font,
+            cell.foreground,
+            Some(cell.background),
+        );
+    }
+
+    fn write_cell(&mut self, cell: Cell) {
+        let line_index = self.scrollback.len() - self.rows() + (self.y - self.padding_y);
+
+        self.scrollback.line_mut(line_index)[self.x - self.padding_x] = cell;
+
+        self.draw_cell(self.x, self.y, cell);
     }
 }
 
@@ -88,15 +309,17 @@ impl Write for Console<'_> {
     }
 
     fn write_char(&mut self, ch: char) -> fmt::Result {
-        if !ch.is_ascii() {
-            self.write_glyph(self.get_glyph_bytes(0));
-        } else if ch != '\n' {
-            self.write_glyph(
-                self.get_glyph_bytes(
-                    (ch as usize * self.font.header.glyph_height as usize)
-                        .rem_euclid(self.font.data.len()),
-                ),
-            );
+        // New output always brings the view back to the bottom
+        if self.scroll_offset != 0 {
+            self.scroll_to_bottom();
+        }
+
+        if ch != '\n' {
+            self.write_cell(Cell {
+                ch,
+                foreground: self.foreground,
+                background: self.background,
+            });
         }
 
         if self.x + 1 >= self.width || ch == '\n' {
@@ -104,18 +327,22 @@ impl Write for Console<'_> {
             self.y += 1;
 
             if self.y >= self.height {
-                let colors = screen::get_colors();
-                let row_unit =
-                    FRAMEBUFFER.width() as usize * self.font.header.glyph_height as usize;
+                if self.is_active {
+                    let colors = screen::get_colors();
+                    let row_unit =
+                        FRAMEBUFFER.width() as usize * self.font.header.glyph_height as usize;
+
+                    for current_row in (self.padding_y..self.height).map(|i| i * row_unit) {
+                        let previous_row = current_row - row_unit;
+                        let next_row = current_row + row_unit;
 
-                for current_row in (self.padding_y..self.height).map(|i| i * row_unit) {
-                    let previous_row = current_row - row_unit;
-                    let next_row = current_row + row_unit;
+                        colors.copy_within(current_row..next_row, previous_row);
+                    }
 
-                    colors.copy_within(current_row..next_row, previous_row);
+                    colors[(self.height - 1) * row_unit..].fill(self.background);
                 }
 
-                colors[(self.height - 1) * row_unit..].fill(self.background);
+                self.scrollback.push_line(self.background);
 
                 self.y = self.height - 1;
             }
@@ -145,18 +372,117 @@ macro_rules! println {
     }};
 }
 
-lazy_static! {
-    pub static ref CONSOLE: Mutex<Console<'static>> = Mutex::new(Console::default());
+static TERMINALS: [Mutex<Option<Console<'static>>>; TERMINAL_COUNT] =
+    [const { Mutex::new(None) }; TERMINAL_COUNT];
+
+static ACTIVE_TERMINAL: AtomicUsize = AtomicUsize::new(KERNEL_LOG_TERMINAL);
+
+/// Held for a whole switch, so switches that run at once can not leave two terminals active
+static TERMINAL_SWITCH: Mutex<()> = Mutex::new(());
+
+/// A locked virtual terminal, the terminal is created on first use
+pub struct TerminalGuard(MutexGuard<'static, Option<Console<'static>>>);
+
+impl Deref for TerminalGuard {
+    type Target = Console<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: `get_terminal` always fills the terminal before handing out a guard
+        unsafe { self.0.as_ref().unwrap_unchecked() }
+    }
+}
+
+impl DerefMut for TerminalGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // SAFETY: `get_terminal` always fills the terminal before handing out a guard
+        unsafe { self.0.as_mut().unwrap_unchecked() }
+    }
+}
+
+/// Locks a virtual terminal, creating it if it was never used before
+pub fn get_terminal(index: usize) -> TerminalGuard {
+    let mut terminal = TERMINALS[index].lock();
+
+    if terminal.is_none() {
+        let mut console = Console::new(if index == KERNEL_LOG_TERMINAL {
+            DEFAULT_SCROLLBACK_LINES
+        } else {
+            TERMINAL_SCROLLBACK_LINES
+        });
+
+        console.is_active = index == ACTIVE_TERMINAL.load(Ordering::Acquire);
+
+        *terminal = Some(console);
+    }
+
+    TerminalGuard(terminal)
+}
+
+/// The index of the virtual terminal that currently owns the framebuffer
+pub fn get_active_terminal() -> usize {
+    ACTIVE_TERMINAL.load(Ordering::Acquire)
+}
+
+/// Gives the framebuffer to another virtual terminal, which is bound to Alt+F1..F12
+pub fn switch_terminal(index: usize) {
+    assert!(
+        index < TERMINAL_COUNT,
+        "there is no virtual terminal {index}"
+    );
+
+    let _switch = TERMINAL_SWITCH.lock();
+
+    let previous_index = ACTIVE_TERMINAL.load(Ordering::Acquire);
+
+    if previous_index == index {
+        return;
+    }
+
+    get_terminal(previous_index).set_active(false);
+
+    // A terminal that is created from now on is created active only if it is the new one
+    ACTIVE_TERMINAL.store(index, Ordering::Release);
+
+    get_terminal(index).set_active(true);
+}
+
+/// A handle to a virtual terminal other than the kernel log terminal, which shells and
+/// processes can write to
+#[derive(Debug, Clone, Copy)]
+pub struct TerminalDevice(usize);
+
+impl TerminalDevice {
+    pub fn open(index: usize) -> Option<TerminalDevice> {
+        (index < TERMINAL_COUNT && index != KERNEL_LOG_TERMINAL).then_some(TerminalDevice(index))
+    }
+
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+impl Write for TerminalDevice {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        get_terminal(self.0).write_str(s)
+    }
 }
 
 #[allow(static_mut_refs)]
 pub fn _print(args: fmt::Arguments) {
-    CONSOLE.lock().write_fmt(args).unwrap();
+    get_terminal(KERNEL_LOG_TERMINAL).write_fmt(args).unwrap();
 }
 
 #[allow(static_mut_refs)]
 pub fn _println(args: fmt::Arguments) {
-    let mut console = CONSOLE.lock();
+    let mut console = get_terminal(KERNEL_LOG_TERMINAL);
     console.write_fmt(args).unwrap();
     console.write_char('\n').unwrap();
 }
+
+/// Dumps the whole scrollback of the kernel log terminal to the serial port, useful to inspect
+/// early boot output after the fact
+pub fn dump_to_serial() {
+    let console = get_terminal(KERNEL_LOG_TERMINAL);
+
+    let _ = console.dump_scrollback(&mut *SERIAL.lock());
+}
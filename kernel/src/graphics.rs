@@ -0,0 +1,429 @@
+use crate::{
+    psf2::Psf2Font,
+    screen::{Color, FRAMEBUFFER},
+};
+
+/// A color with an alpha channel, used by bitmaps that are blended onto a canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Rgba {
+        Rgba { r, g, b, a }
+    }
+
+    /// Blends this color over `destination` using straight (non-premultiplied) alpha
+    #[inline]
+    pub fn blend_over(self, destination: Color) -> Color {
+        match self.a {
+            0 => destination,
+            255 => Color::new(self.r, self.g, self.b),
+            alpha => {
+                let alpha = alpha as u32;
+                let inverse_alpha = 255 - alpha;
+
+                let blend = |source: u8, destination: u8| {
+                    ((source as u32 * alpha + destination as u32 * inverse_alpha + 127) / 255) as u8
+                };
+
+                Color::new(
+                    blend(self.r, destination.r),
+                    blend(self.g, destination.g),
+                    blend(self.b, destination.b),
+                )
+            }
+        }
+    }
+}
+
+impl From<Color> for Rgba {
+    fn from(color: Color) -> Self {
+        Rgba::new(color.r, color.g, color.b, 255)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: isize,
+    pub y: isize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: isize, y: isize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[inline(always)]
+    pub const fn right(&self) -> isize {
+        self.x + self.width as isize
+    }
+
+    #[inline(always)]
+    pub const fn bottom(&self) -> isize {
+        self.y + self.height as isize
+    }
+
+    #[inline(always)]
+    pub const fn contains(&self, x: isize, y: isize) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// The overlapping part of both rectangles, empty if they do not overlap
+    pub fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        Rect::new(
+            x,
+            y,
+            (right - x).max(0) as usize,
+            (bottom - y).max(0) as usize,
+        )
+    }
+
+    #[inline(always)]
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/// A borrowed image made of pixels with an alpha channel
+#[derive(Debug, Clone, Copy)]
+pub struct Bitmap<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [Rgba],
+}
+
+impl<'a> Bitmap<'a> {
+    pub fn new(width: usize, height: usize, pixels: &'a [Rgba]) -> Bitmap<'a> {
+        assert_eq!(pixels.len(), width * height);
+
+        Bitmap {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[inline(always)]
+    pub fn get_pixel(&self, x: usize, y: usize) -> Rgba {
+        self.pixels[x + y * self.width]
+    }
+}
+
+/// A surface of pixels that every drawing operation is clipped to
+pub struct Canvas<'a> {
+    pixels: &'a mut [Color],
+    width: usize,
+    height: usize,
+    /// Amount of pixels between the start of two rows
+    stride: usize,
+    clip: Rect,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(pixels: &'a mut [Color], width: usize, height: usize, stride: usize) -> Canvas<'a> {
+        assert!(stride >= width);
+        assert!(pixels.len() >= stride * height);
+
+        Canvas {
+            pixels,
+            width,
+            height,
+            stride,
+            clip: Rect::new(0, 0, width, height),
+        }
+    }
+
+    #[inline(always)]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline(always)]
+    pub fn clip(&self) -> Rect {
+        self.clip
+    }
+
+    /// Restricts every drawing operation to `clip`, which is itself clipped to the canvas
+    pub fn set_clip(&mut self, clip: Rect) {
+        self.clip = clip.intersect(&Rect::new(0, 0, self.width, self.height));
+    }
+
+    pub fn reset_clip(&mut self) {
+        self.clip = Rect::new(0, 0, self.width, self.height);
+    }
+
+    #[inline(always)]
+    fn index_of(&self, x: isize, y: isize) -> usize {
+        x as usize + y as usize * self.stride
+    }
+
+    #[inline]
+    pub fn get_pixel(&self, x: isize, y: isize) -> Option<Color> {
+        Rect::new(0, 0, self.width, self.height)
+            .contains(x, y)
+            .then(|| self.pixels[self.index_of(x, y)])
+    }
+
+    #[inline]
+    pub fn put_pixel(&mut self, x: isize, y: isize, color: Color) {
+        if self.clip.contains(x, y) {
+            let index = self.index_of(x, y);
+            self.pixels[index] = color;
+        }
+    }
+
+    #[inline]
+    pub fn blend_pixel(&mut self, x: isize, y: isize, color: Rgba) {
+        if self.clip.contains(x, y) {
+            let index = self.index_of(x, y);
+            self.pixels[index] = color.blend_over(self.pixels[index]);
+        }
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        self.fill_rect(self.clip, color);
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let rect = rect.intersect(&self.clip);
+
+        for y in rect.y..rect.bottom() {
+            let start = self.index_of(rect.x, y);
+            self.pixels[start..start + rect.width].fill(color);
+        }
+    }
+
+    /// Draws the outline of `rect` with lines that are `thickness` pixels wide, inside the
+    /// rectangle
+    pub fn draw_rect(&mut self, rect: Rect, thickness: usize, color: Color) {
+        let thickness = thickness.min(rect.width).min(rect.height);
+        let inner_height = rect.height.saturating_sub(thickness * 2);
+
+        self.fill_rect(Rect::new(rect.x, rect.y, rect.width, thickness), color);
+        self.fill_rect(
+            Rect::new(
+                rect.x,
+                rect.bottom() - thickness as isize,
+                rect.width,
+                thickness,
+            ),
+            color,
+        );
+        self.fill_rect(
+            Rect::new(rect.x, rect.y + thickness as isize, thickness, inner_height),
+            color,
+        );
+        self.fill_rect(
+            Rect::new(
+                rect.right() - thickness as isize,
+                rect.y + thickness as isize,
+                thickness,
+                inner_height,
+            ),
+            color,
+        );
+    }
+
+    /// Draws a one pixel wide line between both points, including both of them
+    pub fn draw_line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: Color) {
+        // Bresenham's line algorithm, generalized to every octant
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+
+        let mut error = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+
+        loop {
+            self.put_pixel(x, y, color);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let doubled_error = error * 2;
+
+            if doubled_error >= dy {
+                error += dy;
+                x += step_x;
+            }
+
+            if doubled_error <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Draws the outline of a circle centered at (`cx`, `cy`)
+    pub fn draw_circle(&mut self, cx: isize, cy: isize, radius: usize, color: Color) {
+        // Midpoint circle algorithm, each step draws the eight symmetric points
+        let mut x = radius as isize;
+        let mut y = 0;
+        let mut error = 1 - x;
+
+        while x >= y {
+            for (px, py) in [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ] {
+                self.put_pixel(cx + px, cy + py, color);
+            }
+
+            y += 1;
+
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    pub fn fill_circle(&mut self, cx: isize, cy: isize, radius: usize, color: Color) {
+        let radius = radius as isize;
+
+        for dy in -radius..=radius {
+            // Widest x that still satisfies x² + y² <= r²
+            let half_width = (radius * radius - dy * dy).isqrt();
+
+            self.fill_rect(
+                Rect::new(cx - half_width, cy + dy, (half_width * 2 + 1) as usize, 1),
+                color,
+            );
+        }
+    }
+
+    /// Draws `bitmap` with its top left corner at (`x`, `y`), blending it by its alpha channel
+    pub fn blit(&mut self, x: isize, y: isize, bitmap: &Bitmap) {
+        let area = Rect::new(x, y, bitmap.width, bitmap.height).intersect(&self.clip);
+
+        for dst_y in area.y..area.bottom() {
+            for dst_x in area.x..area.right() {
+                let pixel = bitmap.get_pixel((dst_x - x) as usize, (dst_y - y) as usize);
+                let index = self.index_of(dst_x, dst_y);
+
+                self.pixels[index] = pixel.blend_over(self.pixels[index]);
+            }
+        }
+    }
+
+    /// Draws `bitmap` stretched into `destination` using nearest neighbor sampling
+    pub fn blit_scaled(&mut self, destination: Rect, bitmap: &Bitmap) {
+        if destination.is_empty() || bitmap.width == 0 || bitmap.height == 0 {
+            return;
+        }
+
+        let area = destination.intersect(&self.clip);
+
+        for dst_y in area.y..area.bottom() {
+            let src_y = (dst_y - destination.y) as usize * bitmap.height / destination.height;
+
+            for dst_x in area.x..area.right() {
+                let src_x = (dst_x - destination.x) as usize * bitmap.width / destination.width;
+
+                let index = self.index_of(dst_x, dst_y);
+
+                self.pixels[index] = bitmap
+                    .get_pixel(src_x, src_y)
+                    .blend_over(self.pixels[index]);
+            }
+        }
+    }
+
+    /// Draws a single character with its top left corner at (`x`, `y`), the glyph's
+    /// background is left untouched if `background` is `None`
+    pub fn draw_char(
+        &mut self,
+        x: isize,
+        y: isize,
+        ch: char,
+        font: &Psf2Font,
+        foreground: Color,
+        background: Option<Color>,
+    ) {
+        let glyph = font.get_glyph(ch);
+
+        for dy in 0..font.header.glyph_height as usize {
+            for dx in 0..font.header.glyph_width as usize {
+                if font.get_glyph_bit(glyph, dx, dy) {
+                    self.put_pixel(x + dx as isize, y + dy as isize, foreground);
+                } else if let Some(background) = background {
+                    self.put_pixel(x + dx as isize, y + dy as isize, background);
+                }
+            }
+        }
+    }
+
+    /// Draws `text` starting at (`x`, `y`), a newline goes back to `x` on the next line
+    pub fn draw_text(
+        &mut self,
+        x: isize,
+        y: isize,
+        text: &str,
+        font: &Psf2Font,
+        foreground: Color,
+        background: Option<Color>,
+    ) {
+        let glyph_width = font.header.glyph_width as isize;
+        let glyph_height = font.header.glyph_height as isize;
+
+        let (mut pen_x, mut pen_y) = (x, y);
+
+        for ch in text.chars() {
+            if ch == '\n' {
+                pen_x = x;
+                pen_y += glyph_height;
+                continue;
+            }
+
+            self.draw_char(pen_x, pen_y, ch, font, foreground, background);
+
+            pen_x += glyph_width;
+        }
+    }
+}
+
+/// A canvas covering the whole framebuffer
+pub fn get_screen_canvas() -> Canvas<'static> {
+    let width = FRAMEBUFFER.width() as usize;
+    let height = FRAMEBUFFER.height() as usize;
+    let stride = FRAMEBUFFER.pitch() as usize / size_of::<Color>();
+
+    let pixels = unsafe {
+        core::slice::from_raw_parts_mut(FRAMEBUFFER.addr().cast::<Color>(), stride * height)
+    };
+
+    Canvas::new(pixels, width, height, stride)
+}
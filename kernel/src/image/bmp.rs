@@ -0,0 +1,117 @@
+use crate::graphics::Rgba;
+
+use super::{DecodeError, Image, alloc_pixels, check_dimensions, read_u16_le, read_u32_le};
+
+pub const BMP_MAGIC: [u8; 2] = *b"BM";
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+/// Extracts a channel from a pixel using the channel's mask from the header
+#[inline(always)]
+fn extract_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+
+    let shift = mask.trailing_zeros();
+    let width = (mask >> shift).trailing_ones();
+
+    let channel = (value & mask) >> shift;
+
+    // Scale the channel to 8 bits, most masks are already 8 bits wide
+    if width >= 8 {
+        (channel >> (width - 8)) as u8
+    } else {
+        ((channel * 255) / ((1 << width) - 1)) as u8
+    }
+}
+
+/// Decodes an uncompressed Windows bitmap with 24 or 32 bits per pixel
+pub fn decode(data: &[u8]) -> Result<Image, DecodeError> {
+    if !data.starts_with(&BMP_MAGIC) {
+        return Err(DecodeError::UnknownFormat);
+    }
+
+    let pixels_offset = read_u32_le(data, 10)? as usize;
+    let info_header_size = read_u32_le(data, 14)?;
+
+    // BITMAPCOREHEADER is the only header with 16-bit dimensions, and nobody uses it anymore
+    if info_header_size < 40 {
+        return Err(DecodeError::Unsupported);
+    }
+
+    let width = read_u32_le(data, 18)? as i32;
+    let height = read_u32_le(data, 22)? as i32;
+    let bits_per_pixel = read_u16_le(data, 28)?;
+    let compression = read_u32_le(data, 30)?;
+
+    if width <= 0 || height == 0 {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    // A negative height means that the rows are stored from top to bottom
+    let is_top_down = height < 0;
+
+    let width = width as usize;
+    let height = height.unsigned_abs() as usize;
+
+    check_dimensions(width, height)?;
+
+    let (red_mask, green_mask, blue_mask, alpha_mask) = match (compression, bits_per_pixel) {
+        (BI_RGB, 24) => (0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0),
+        // The fourth byte of a BI_RGB 32-bit pixel is unused, so the image is opaque
+        (BI_RGB, 32) => (0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0),
+        (BI_BITFIELDS, 32) => (
+            read_u32_le(data, 54)?,
+            read_u32_le(data, 58)?,
+            read_u32_le(data, 62)?,
+            // Only BITMAPV4HEADER and newer have an alpha mask
+            if info_header_size >= 56 {
+                read_u32_le(data, 66)?
+            } else {
+                0
+            },
+        ),
+        _ => return Err(DecodeError::Unsupported),
+    };
+
+    let bytes_per_pixel = bits_per_pixel as usize / 8;
+    // Rows are padded to a multiple of 4 bytes
+    let row_size = (width * bytes_per_pixel).next_multiple_of(4);
+
+    let pixel_data = data
+        .get(pixels_offset..pixels_offset + row_size * height)
+        .ok_or(DecodeError::Truncated)?;
+
+    let mut pixels = alloc_pixels(width * height)?;
+
+    for y in 0..height {
+        let row_index = if is_top_down { y } else { height - 1 - y };
+        let row = &pixel_data[row_index * row_size..][..width * bytes_per_pixel];
+
+        pixels.extend(row.chunks_exact(bytes_per_pixel).map(|bytes| {
+            let value = bytes
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| (value << 8) | byte as u32);
+
+            Rgba::new(
+                extract_channel(value, red_mask),
+                extract_channel(value, green_mask),
+                extract_channel(value, blue_mask),
+                if alpha_mask == 0 {
+                    255
+                } else {
+                    extract_channel(value, alpha_mask)
+                },
+            )
+        }));
+    }
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
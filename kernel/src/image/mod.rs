@@ -0,0 +1,94 @@
+pub mod bmp;
+pub mod ppm;
+pub mod qoi;
+
+use alloc::vec::Vec;
+
+use crate::graphics::{Bitmap, Rgba};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data does not start with the magic of any supported format
+    UnknownFormat,
+    /// The header is malformed or describes an image that can not exist
+    InvalidHeader,
+    /// The header is valid but uses a feature that we do not decode
+    Unsupported,
+    /// The data ended before the whole image was decoded
+    Truncated,
+    /// There is not enough memory for the pixels of the image
+    OutOfMemory,
+}
+
+/// A decoded image that owns its pixels
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Rgba>,
+}
+
+impl Image {
+    /// Decodes an image by guessing its format from its magic
+    pub fn decode(data: &[u8]) -> Result<Image, DecodeError> {
+        if data.starts_with(&qoi::QOI_MAGIC) {
+            qoi::decode(data)
+        } else if data.starts_with(&bmp::BMP_MAGIC) {
+            bmp::decode(data)
+        } else {
+            Err(DecodeError::UnknownFormat)
+        }
+    }
+
+    pub fn as_bitmap(&self) -> Bitmap<'_> {
+        Bitmap::new(self.width, self.height, &self.pixels)
+    }
+}
+
+#[inline(always)]
+fn read_u16_le(data: &[u8], offset: usize) -> Result<u16, DecodeError> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(DecodeError::Truncated)
+}
+
+#[inline(always)]
+fn read_u32_le(data: &[u8], offset: usize) -> Result<u32, DecodeError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(DecodeError::Truncated)
+}
+
+#[inline(always)]
+fn read_u32_be(data: &[u8], offset: usize) -> Result<u32, DecodeError> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(DecodeError::Truncated)
+}
+
+/// Allocates room for every pixel of an image up front, which fails instead of panicking
+/// because the dimensions come from the image itself
+fn alloc_pixels(pixel_count: usize) -> Result<Vec<Rgba>, DecodeError> {
+    let mut pixels = Vec::new();
+
+    pixels
+        .try_reserve_exact(pixel_count)
+        .map_err(|_| DecodeError::OutOfMemory)?;
+
+    Ok(pixels)
+}
+
+/// Rejects images that are empty or whose pixels would take an unreasonable amount of memory
+fn check_dimensions(width: usize, height: usize) -> Result<(), DecodeError> {
+    const MAX_PIXEL_COUNT: usize = 64 * 1024 * 1024;
+
+    if width == 0
+        || height == 0
+        || width
+            .checked_mul(height)
+            .is_none_or(|c| c > MAX_PIXEL_COUNT)
+    {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use core::fmt::Write;
+
+use crate::graphics::Rgba;
+
+/// Large enough for the header of any image whose dimensions fit in 32 bits
+const MAX_HEADER_SIZE: usize = 32;
+
+/// Encodes an image as a binary portable pixmap, `get_pixel` is called for every pixel from
+/// left to right then top to bottom, and the encoded bytes are streamed to `output` so nothing
+/// needs to be allocated
+pub fn encode<P, O>(width: usize, height: usize, mut get_pixel: P, mut output: O)
+where
+    P: FnMut(usize, usize) -> Rgba,
+    O: FnMut(&[u8]),
+{
+    let mut header = HeaderBuffer([0; MAX_HEADER_SIZE], 0);
+
+    let _ = write!(header, "P6\n{width} {height}\n255\n");
+
+    output(&header.0[..header.1]);
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = get_pixel(x, y);
+
+            output(&[pixel.r, pixel.g, pixel.b]);
+        }
+    }
+}
+
+struct HeaderBuffer([u8; MAX_HEADER_SIZE], usize);
+
+impl Write for HeaderBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let bytes = s.as_bytes();
+
+        self.0
+            .get_mut(self.1..self.1 + bytes.len())
+            .ok_or(core::fmt::Error)?
+            .copy_from_slice(bytes);
+
+        self.1 += bytes.len();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use crate::graphics::Rgba;
+
+use super::{DecodeError, Image, alloc_pixels, check_dimensions, read_u32_be};
+
+pub const QOI_MAGIC: [u8; 4] = *b"qoif";
+
+const HEADER_SIZE: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX: u8 = 0b0000_0000;
+const OP_DIFF: u8 = 0b0100_0000;
+const OP_LUMA: u8 = 0b1000_0000;
+const OP_RUN: u8 = 0b1100_0000;
+const OP_RGB: u8 = 0b1111_1110;
+const OP_RGBA: u8 = 0b1111_1111;
+
+const OP_MASK: u8 = 0b1100_0000;
+
+#[inline(always)]
+fn hash(pixel: Rgba) -> usize {
+    (pixel.r as usize * 3 + pixel.g as usize * 5 + pixel.b as usize * 7 + pixel.a as usize * 11)
+        % 64
+}
+
+/// Decodes a Quite OK Image, as described in <https://qoiformat.org/qoi-specification.pdf>
+pub fn decode(data: &[u8]) -> Result<Image, DecodeError> {
+    if !data.starts_with(&QOI_MAGIC) {
+        return Err(DecodeError::UnknownFormat);
+    }
+
+    let width = read_u32_be(data, 4)? as usize;
+    let height = read_u32_be(data, 8)? as usize;
+    let channels = *data.get(12).ok_or(DecodeError::Truncated)?;
+
+    if channels != 3 && channels != 4 {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    check_dimensions(width, height)?;
+
+    let pixel_count = width * height;
+
+    let mut pixels = alloc_pixels(pixel_count)?;
+    let mut seen = [Rgba::new(0, 0, 0, 0); 64];
+    let mut pixel = Rgba::new(0, 0, 0, 255);
+
+    let chunks = data
+        .get(HEADER_SIZE..data.len().saturating_sub(END_MARKER.len()))
+        .ok_or(DecodeError::Truncated)?;
+
+    let mut position = 0;
+
+    let mut next_byte = || {
+        let byte = chunks.get(position).copied().ok_or(DecodeError::Truncated);
+        position += 1;
+        byte
+    };
+
+    while pixels.len() < pixel_count {
+        let tag = next_byte()?;
+
+        match tag {
+            OP_RGB => {
+                pixel.r = next_byte()?;
+                pixel.g = next_byte()?;
+                pixel.b = next_byte()?;
+            }
+
+            OP_RGBA => {
+                pixel.r = next_byte()?;
+                pixel.g = next_byte()?;
+                pixel.b = next_byte()?;
+                pixel.a = next_byte()?;
+            }
+
+            _ => match tag & OP_MASK {
+                OP_INDEX => pixel = seen[(tag & !OP_MASK) as usize],
+
+                OP_DIFF => {
+                    pixel.r = pixel.r.wrapping_add(((tag >> 4) & 0b11).wrapping_sub(2));
+                    pixel.g = pixel.g.wrapping_add(((tag >> 2) & 0b11).wrapping_sub(2));
+                    pixel.b = pixel.b.wrapping_add((tag & 0b11).wrapping_sub(2));
+                }
+
+                OP_LUMA => {
+                    let second = next_byte()?;
+                    let green_diff = (tag & !OP_MASK).wrapping_sub(32);
+
+                    pixel.r = pixel
+                        .r
+                        .wrapping_add(green_diff.wrapping_add((second >> 4).wrapping_sub(8)));
+                    pixel.g = pixel.g.wrapping_add(green_diff);
+                    pixel.b = pixel
+                        .b
+                        .wrapping_add(green_diff.wrapping_add((second & 0b1111).wrapping_sub(8)));
+                }
+
+                OP_RUN => {
+                    let run = ((tag & !OP_MASK) as usize + 1).min(pixel_count - pixels.len());
+
+                    seen[hash(pixel)] = pixel;
+
+                    pixels.extend(core::iter::repeat_n(pixel, run));
+
+                    continue;
+                }
+
+                _ => unreachable!(),
+            },
+        }
+
+        seen[hash(pixel)] = pixel;
+
+        pixels.push(pixel);
+    }
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Encodes an opaque image as a Quite OK Image, `get_pixel` is called for every pixel from left
+/// to right then top to bottom, and the encoded bytes are streamed to `output` so nothing needs
+/// to be allocated
+pub fn encode<P, O>(width: usize, height: usize, mut get_pixel: P, mut output: O)
+where
+    P: FnMut(usize, usize) -> Rgba,
+    O: FnMut(&[u8]),
+{
+    output(&QOI_MAGIC);
+    output(&(width as u32).to_be_bytes());
+    output(&(height as u32).to_be_bytes());
+    // Three channels, sRGB with linear alpha
+    output(&[3, 0]);
+
+    let mut seen = [Rgba::new(0, 0, 0, 0); 64];
+    let mut previous = Rgba::new(0, 0, 0, 255);
+    let mut run = 0u8;
+
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = get_pixel(x, y);
+
+            if pixel == previous {
+                run += 1;
+
+                if run == 62 {
+                    output(&[OP_RUN | (run - 1)]);
+                    run = 0;
+                }
+
+                continue;
+            }
+
+            if run > 0 {
+                output(&[OP_RUN | (run - 1)]);
+                run = 0;
+            }
+
+            let index = hash(pixel);
+
+            if seen[index] == pixel {
+                output(&[OP_INDEX | index as u8]);
+            } else {
+                seen[index] = pixel;
+
+                let red_diff = pixel.r.wrapping_sub(previous.r) as i8;
+                let green_diff = pixel.g.wrapping_sub(previous.g) as i8;
+                let blue_diff = pixel.b.wrapping_sub(previous.b) as i8;
+
+                let red_green_diff = red_diff.wrapping_sub(green_diff);
+                let blue_green_diff = blue_diff.wrapping_sub(green_diff);
+
+                if pixel.a != previous.a {
+                    output(&[OP_RGBA, pixel.r, pixel.g, pixel.b, pixel.a]);
+                } else if (-2..=1).contains(&red_diff)
+                    && (-2..=1).contains(&green_diff)
+                    && (-2..=1).contains(&blue_diff)
+                {
+                    output(&[OP_DIFF
+                        | ((red_diff + 2) as u8) << 4
+                        | ((green_diff + 2) as u8) << 2
+                        | (blue_diff + 2) as u8]);
+                } else if (-32..=31).contains(&green_diff)
+                    && (-8..=7).contains(&red_green_diff)
+                    && (-8..=7).contains(&blue_green_diff)
+                {
+                    output(&[
+                        OP_LUMA | (green_diff + 32) as u8,
+                        ((red_green_diff + 8) as u8) << 4 | (blue_green_diff + 8) as u8,
+                    ]);
+                } else {
+                    output(&[OP_RGB, pixel.r, pixel.g, pixel.b]);
+                }
+            }
+
+            previous = pixel;
+        }
+    }
+
+    if run > 0 {
+        output(&[OP_RUN | (run - 1)]);
+    }
+
+    output(&END_MARKER);
+}
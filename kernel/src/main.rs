@@ -1,4 +1,4 @@
-#![feature(abi_x86_interrupt, allocator_api)]
+#![feature(abi_x86_interrupt, alloc_error_handler, allocator_api)]
 #![no_std]
 #![no_main]
 
@@ -10,6 +10,9 @@ pub mod console;
 pub mod acpi;
 pub mod allocators;
 pub mod arch;
+pub mod cmdline;
+pub mod graphics;
+pub mod image;
 pub mod memory;
 pub mod mp;
 pub mod paging;
@@ -17,6 +20,8 @@ pub mod panic;
 pub mod psf2;
 pub mod requests;
 pub mod screen;
+pub mod screenshot;
+pub mod splash;
 
 /// Initialize bootstrap processor
 #[unsafe(no_mangle)]
@@ -27,12 +32,62 @@ extern "C" fn init_bsp() -> ! {
 
     arch::interrupts::disable();
 
+    memory::init();
+
+    paging::init();
+
+    // The stack that limine gave us has no guard page, and it is reclaimed with the rest of
+    // the bootloader's memory
+    mp::switch_to_kernel_stack(start_bsp, 0);
+}
+
+/// Initialize bootstrap processor on its kernel stack
+extern "C" fn start_bsp(_: usize) -> ! {
+    splash::show();
+
     arch::init_bsp();
 
+    // Application processors send us TLB shootdowns while they boot, which are interrupts
+    arch::tlb::init();
+    arch::interrupts::enable();
+
+    // The ACPI tables were parsed while initializing the I/O APICs
+    memory::reclaim_acpi_memory();
+
     mp::boot_ap();
 
+    splash::finish();
+
+    memory::reclaim_bootloader_memory();
+
+    #[cfg(feature = "self_checks")]
+    {
+        memory::run_self_checks();
+        paging::run_self_checks();
+    }
+
+    #[cfg(feature = "benchmarks")]
+    memory::run_benchmarks();
+
+    if cmdline::has_flag(memory::MEMINFO_FLAG) {
+        memory::print_stats();
+    }
+
+    #[cfg(feature = "alloc_tracking")]
+    if cmdline::has_flag(memory::ALLOCATIONS_FLAG) {
+        memory::dump_allocations();
+    }
+
+    screenshot::dump_if_requested(screenshot::ON_BOOT_FLAG);
+
     loop {
         arch::interrupts::wait_for_interrupts();
+
+        // Switching is not done by the keyboard interrupt, which may have interrupted us while
+        // we were holding the lock of a terminal
+        if let Some(terminal) = arch::keyboard::take_requested_terminal() {
+            console::switch_terminal(terminal);
+        }
     }
 }
 
@@ -40,6 +95,9 @@ extern "C" fn init_bsp() -> ! {
 fn init_ap(cpu_id: u32) -> ! {
     arch::init_ap(cpu_id);
 
+    arch::tlb::init();
+    arch::interrupts::enable();
+
     loop {
         arch::interrupts::wait_for_interrupts();
     }
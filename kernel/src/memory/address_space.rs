@@ -0,0 +1,402 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    allocators::buddy_allocator::Frame,
+    arch::{
+        cpu,
+        tlb::{self, TlbShootdown},
+    },
+    mp::MAX_CPU_COUNT,
+    paging::{self, LOWER_HALF_END, MIN_PAGE_SIZE, MapError, PageFault, PageTable},
+};
+
+use super::{
+    PageFaultError, Region, RegionFlags, align_down, alloc_frame, free_frame, get_frame_ref_count,
+    share_frames,
+    vmm::{self, GUARD_SIZE},
+};
+
+/// The address space that each CPU is using, or `None` if it is using the kernel's page table,
+/// holding it here keeps it alive for as long as it is used
+static ACTIVE_ADDRESS_SPACES: [Mutex<Option<Arc<AddressSpace>>>; MAX_CPU_COUNT] =
+    [const { Mutex::new(None) }; MAX_CPU_COUNT];
+
+/// The virtual addresses of a process, user pages are mapped in the lower half, and the higher
+/// half is shared with the kernel's page table
+#[derive(Debug)]
+pub struct AddressSpace {
+    p4_frame: Frame,
+    /// The CPUs that are using this address space, which take its shootdowns
+    active_cpus: AtomicU64,
+    /// The regions whose frames are allocated on their first access, sorted by their start,
+    /// the lock is held while page tables are changed
+    regions: Mutex<Vec<Region>>,
+}
+
+impl AddressSpace {
+    /// Creates an address space that has no user pages, returns `None` if there is no memory for
+    /// its level 4 table
+    pub fn new() -> Option<Arc<AddressSpace>> {
+        let p4_frame = alloc_frame()?;
+        let p4_table = unsafe { &mut *(p4_frame.virt() as *mut PageTable) };
+
+        *p4_table = PageTable::empty();
+
+        p4_table.share_higher_half_of(paging::get_kernel_table());
+
+        Some(Arc::new(AddressSpace {
+            p4_frame,
+            active_cpus: AtomicU64::new(0),
+            regions: Mutex::new(Vec::new()),
+        }))
+    }
+
+    #[inline(always)]
+    fn get_page_table(&self) -> &'static mut PageTable {
+        unsafe { &mut *(self.p4_frame.virt() as *mut PageTable) }
+    }
+
+    /// Whether the CPU we are running on is using this address space
+    #[inline(always)]
+    pub fn is_active(&self) -> bool {
+        core::ptr::eq(paging::get_active_table(), self.get_page_table())
+    }
+
+    /// Switches the CPU we are running on to this address space, which is done on every context
+    /// switch to a thread that runs in it
+    pub fn activate(self: &Arc<Self>) {
+        let mut active = get_active_slot().lock();
+
+        // The CPU takes our shootdowns before it can cache any of our translations
+        self.active_cpus
+            .fetch_or(tlb::get_current_cpu_bit(), Ordering::SeqCst);
+
+        if !self.is_active() {
+            paging::set_active_table(self.get_page_table());
+        }
+
+        if let Some(previous) = active.replace(self.clone())
+            && !Arc::ptr_eq(&previous, self)
+        {
+            previous
+                .active_cpus
+                .fetch_and(!tlb::get_current_cpu_bit(), Ordering::SeqCst);
+        }
+    }
+
+    /// Switches the CPU we are running on back to the kernel's page table
+    pub fn deactivate() {
+        let mut active = get_active_slot().lock();
+
+        paging::set_active_table(paging::get_kernel_table());
+
+        if let Some(previous) = active.take() {
+            previous
+                .active_cpus
+                .fetch_and(!tlb::get_current_cpu_bit(), Ordering::SeqCst);
+        }
+    }
+
+    /// A shootdown for the CPUs that are using this address space
+    #[inline(always)]
+    fn shootdown(&self) -> TlbShootdown {
+        TlbShootdown::new(self.active_cpus.load(Ordering::SeqCst))
+    }
+
+    /// The address space that the CPU we are running on is using
+    pub fn get_active() -> Option<Arc<AddressSpace>> {
+        get_active_slot().lock().clone()
+    }
+
+    pub fn translate(&self, virt: usize) -> Option<usize> {
+        self.get_page_table().translate(virt)
+    }
+
+    /// Maps the user page at `virt` to the frame at `phys`, the frame is not freed with the
+    /// address space, and clones of the address space keep sharing it
+    pub fn map(&self, virt: usize, phys: usize, flags: RegionFlags) -> Result<(), MapError> {
+        assert_user_page(virt);
+
+        let _regions = self.regions.lock();
+
+        // The page may have been mapped to another frame, which is flushed once the flags are set
+        let mut shootdown = self.shootdown();
+
+        let entry = self.get_page_table().try_map(virt, phys, &mut shootdown)?;
+
+        set_flags(entry, flags);
+
+        Ok(())
+    }
+
+    /// Unmaps the user page at `virt`, returns the physical address that it was mapped to, so
+    /// the frame can be freed, or `None` if it was not mapped
+    pub fn unmap(&self, virt: usize) -> Option<usize> {
+        assert_user_page(virt);
+
+        let _regions = self.regions.lock();
+
+        let page_table = self.get_page_table();
+
+        let phys = page_table.translate(virt)?;
+
+        // No CPU may access the frame once it is freed
+        page_table.unmap(virt, &mut self.shootdown());
+
+        Some(phys)
+    }
+
+    /// Changes the flags of the user page at `virt`, returns `false` if it is not mapped
+    pub fn protect(&self, virt: usize, flags: RegionFlags) -> bool {
+        assert_user_page(virt);
+
+        let _regions = self.regions.lock();
+
+        let mut shootdown = self.shootdown();
+
+        let Some(entry) = self.get_page_table().get_mapped(virt, &mut shootdown) else {
+            return false;
+        };
+
+        set_flags(entry, flags);
+
+        shootdown.add(virt);
+
+        true
+    }
+
+    /// Records a region of `size` bytes at `start` whose pages are backed by zeroed frames on
+    /// their first access, returns `false` if it overlaps another region or its guard page
+    pub fn map_lazy(
+        &self,
+        start: usize,
+        size: usize,
+        name: &'static str,
+        flags: RegionFlags,
+    ) -> bool {
+        assert_user_page(start);
+
+        let page_count = size.div_ceil(MIN_PAGE_SIZE);
+
+        let fits = page_count
+            .checked_mul(MIN_PAGE_SIZE)
+            .and_then(|size| size.checked_add(GUARD_SIZE))
+            .is_some_and(|size| size <= LOWER_HALF_END - start);
+
+        if !fits {
+            return false;
+        }
+
+        let region = Region {
+            start,
+            page_count,
+            name,
+            flags,
+            owns_frames: true,
+            is_stack: false,
+        };
+
+        vmm::insert_region(&mut self.regions.lock(), region)
+    }
+
+    /// Unmaps the region that starts at `start`, the frames that backed its pages are freed,
+    /// returns `false` if no region starts there
+    pub fn unmap_lazy(&self, start: usize) -> bool {
+        let mut regions = self.regions.lock();
+
+        let Ok(index) = regions.binary_search_by_key(&start, |region| region.start) else {
+            return false;
+        };
+
+        let region = regions.remove(index);
+
+        vmm::unmap_pages(
+            self.get_page_table(),
+            region.start..region.end(),
+            true,
+            self.active_cpus.load(Ordering::SeqCst),
+        );
+
+        true
+    }
+
+    /// Creates an address space that maps the same user pages, the frames of lazily mapped
+    /// regions are shared until either address space writes to them, and other frames are
+    /// shared for good, so only page tables are copied, returns `None` if there is no memory
+    /// for them
+    pub fn try_clone(&self) -> Option<Arc<AddressSpace>> {
+        let regions = self.regions.lock();
+
+        let copy = AddressSpace::new()?;
+
+        // The regions are known before copying, so dropping a partial copy lets go of the
+        // frames that were shared with it
+        *copy.regions.lock() = regions.clone();
+
+        let result = self.get_page_table().try_copy_lower_half(
+            copy.get_page_table(),
+            &mut |virt, entry, copied_entry| {
+                let is_lazy =
+                    vmm::find_preceding(&regions, virt).is_some_and(|region| virt < region.end());
+
+                if is_lazy {
+                    share_frames(Frame::from_phys(entry.get_phys() as usize));
+
+                    entry.set_writable(false).set_copy_on_write(true);
+
+                    *copied_entry = *entry;
+                }
+            },
+        );
+
+        // Pages that became read only must not stay writable in the TLB
+        self.shootdown().add_everything();
+
+        result.ok().map(|_| copy)
+    }
+
+    /// Backs the page of a lazily mapped region that `fault` accessed with a zeroed frame, or
+    /// gives it a frame of its own if it wrote to a frame that it shares
+    pub fn handle_page_fault(&self, fault: &PageFault) -> Result<(), PageFaultError> {
+        let regions = self.regions.lock();
+
+        let Some(region) = vmm::find_preceding(&regions, fault.address) else {
+            return Err(PageFaultError::NotMapped);
+        };
+
+        if region.is_in_guard(fault.address) {
+            return Err(PageFaultError::Guard(region.name));
+        }
+
+        if fault.address >= region.end() {
+            return Err(PageFaultError::NotMapped);
+        }
+
+        if (fault.is_write && !region.flags.is_writable)
+            || (fault.is_instruction_fetch && !region.flags.is_executable)
+        {
+            return Err(PageFaultError::NotAllowed);
+        }
+
+        let virt = align_down(fault.address, MIN_PAGE_SIZE);
+
+        let mut shootdown = self.shootdown();
+
+        if let Some(entry) = self.get_page_table().get_mapped(virt, &mut shootdown) {
+            if fault.is_write && entry.is_copy_on_write() {
+                return copy_on_write(entry, virt, shootdown);
+            }
+
+            // Another CPU may have backed or copied the page while we were waiting for the lock
+            let is_allowed = (!fault.is_write || entry.is_writable())
+                && (!fault.is_instruction_fetch || entry.is_executable());
+
+            return if is_allowed {
+                Ok(())
+            } else {
+                Err(PageFaultError::NotAllowed)
+            };
+        }
+
+        let frame = alloc_frame().ok_or(PageFaultError::OutOfMemory)?;
+
+        unsafe { (frame.virt() as *mut u8).write_bytes(0, MIN_PAGE_SIZE) };
+
+        match self
+            .get_page_table()
+            .try_map(virt, frame.phys(), &mut shootdown)
+        {
+            Ok(entry) => {
+                set_flags(entry, region.flags);
+
+                Ok(())
+            }
+
+            Err(_) => {
+                free_frame(frame);
+
+                Err(PageFaultError::OutOfMemory)
+            }
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        let page_table = self.get_page_table();
+
+        // No CPU is using the address space, as they hold a reference to it while they do
+        for region in self.regions.get_mut().iter() {
+            vmm::unmap_pages(page_table, region.start..region.end(), true, 0);
+        }
+
+        page_table.free_lower_half();
+
+        free_frame(self.p4_frame);
+    }
+}
+
+#[inline(always)]
+fn get_active_slot() -> &'static Mutex<Option<Arc<AddressSpace>>> {
+    let cpu_id = cpu::get_current_id().expect("the cpu is not initialized");
+
+    &ACTIVE_ADDRESS_SPACES[cpu_id]
+}
+
+/// Gives the page at `virt` a frame of its own, which is only a copy if the frame is still
+/// shared with another address space
+fn copy_on_write(
+    entry: &mut paging::Entry,
+    virt: usize,
+    mut shootdown: TlbShootdown,
+) -> Result<(), PageFaultError> {
+    let frame = Frame::from_phys(entry.get_phys() as usize);
+
+    let is_shared = get_frame_ref_count(frame) > 1;
+
+    if is_shared {
+        let copy = alloc_frame().ok_or(PageFaultError::OutOfMemory)?;
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                frame.virt() as *const u8,
+                copy.virt() as *mut u8,
+                MIN_PAGE_SIZE,
+            );
+        }
+
+        entry.set_phys(copy.phys() as u64);
+    }
+
+    entry.set_writable(true).set_copy_on_write(false);
+
+    shootdown.add(virt);
+    shootdown.flush();
+
+    // The other address spaces keep the frame
+    if is_shared {
+        free_frame(frame);
+    }
+
+    Ok(())
+}
+
+#[inline(always)]
+fn assert_user_page(virt: usize) {
+    assert!(
+        virt < LOWER_HALF_END && virt.is_multiple_of(MIN_PAGE_SIZE),
+        "{virt:#x} is not the address of a user page"
+    );
+}
+
+#[inline(always)]
+fn set_flags(entry: &mut paging::Entry, flags: RegionFlags) {
+    entry
+        .set_writable(flags.is_writable)
+        .set_executability(flags.is_executable)
+        .set_cache_type(flags.cache_type)
+        .set_user_accessible(true);
+}
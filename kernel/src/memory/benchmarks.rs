@@ -0,0 +1,114 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+use crate::{
+    allocators::{
+        buddy_allocator::{self, Frame},
+        page_allocator::PageAllocator,
+    },
+    arch::cpu,
+    paging::MIN_PAGE_SIZE,
+};
+
+use super::{alloc_frames, free_frames};
+
+/// The page allocator is measured on the bitmap of a 4 GiB arena, only the bitmap is backed by
+/// frames, as the pages that are handed out are never accessed
+const PAGE_ARENA_LEN: usize = 4 * 1024 * 1024 * 1024;
+
+const PAGE_ALLOCATION_COUNT: usize = 20000;
+
+/// Allocations are runs of 1 to this many pages
+const MAX_RUN_PAGE_COUNT: usize = 16;
+
+/// Allocations that are alive at once, a random one of them is freed before each allocation, so
+/// the bitmap gets fragmented like it does in a long running heap
+const LIVE_ALLOCATION_COUNT: usize = 1024;
+
+/// Measures the allocators and prints how long they took
+pub fn run_benchmarks() {
+    benchmark_page_allocator();
+}
+
+/// Compares scanning the bitmap a word at a time with the bit by bit scan it replaced
+fn benchmark_page_allocator() {
+    let bitmap_order = buddy_allocator::get_order_of(PageAllocator::get_bitmap_page_count(
+        PAGE_ARENA_LEN / MIN_PAGE_SIZE,
+    ));
+
+    let Some(bitmap) = alloc_frames(bitmap_order) else {
+        println!("benchmark: no free block for the page allocator bitmap, skipped");
+        return;
+    };
+
+    let (word_cycles, word_failed_count) = run_page_allocations(bitmap, PageAllocator::alloc);
+    let (bit_cycles, bit_failed_count) =
+        run_page_allocations(bitmap, PageAllocator::alloc_bit_by_bit);
+
+    free_frames(bitmap, bitmap_order);
+
+    println!(
+        "benchmark: {PAGE_ALLOCATION_COUNT} page allocations of 1 to {MAX_RUN_PAGE_COUNT} pages in a {} GiB arena",
+        PAGE_ARENA_LEN / (1024 * 1024 * 1024),
+    );
+
+    println!(
+        "  scanning words took {word_cycles} cycles, {} per allocation, {word_failed_count} failed",
+        word_cycles / PAGE_ALLOCATION_COUNT as u64,
+    );
+
+    println!(
+        "  scanning bits took {bit_cycles} cycles, {} per allocation, {bit_failed_count} failed",
+        bit_cycles / PAGE_ALLOCATION_COUNT as u64,
+    );
+
+    println!(
+        "  scanning words is {} times faster",
+        bit_cycles / word_cycles.max(1)
+    );
+}
+
+/// Runs the same allocations and frees with `alloc` on a new arena whose bitmap is in `bitmap`,
+/// returns how many cycles they took and how many allocations failed
+fn run_page_allocations(
+    bitmap: Frame,
+    alloc: fn(&mut PageAllocator, Layout) -> *mut u8,
+) -> (u64, usize) {
+    let mut page_allocator = PageAllocator::new(
+        NonNull::new(bitmap.virt() as *mut u8).unwrap(),
+        PAGE_ARENA_LEN,
+    );
+
+    let mut live_allocations = [None; LIVE_ALLOCATION_COUNT];
+    let mut random_state = 0x2545_f491_4f6c_dd1d;
+    let mut failed_count = 0;
+
+    let start = cpu::read_timestamp_counter();
+
+    for _ in 0..PAGE_ALLOCATION_COUNT {
+        let slot = &mut live_allocations[get_random(&mut random_state) % LIVE_ALLOCATION_COUNT];
+
+        if let Some((ptr, layout)) = slot.take() {
+            page_allocator.dealloc(ptr, layout);
+        }
+
+        let page_count = get_random(&mut random_state) % MAX_RUN_PAGE_COUNT + 1;
+        let layout = Layout::from_size_align(page_count * MIN_PAGE_SIZE, MIN_PAGE_SIZE).unwrap();
+
+        match alloc(&mut page_allocator, layout) {
+            ptr if ptr.is_null() => failed_count += 1,
+            ptr => *slot = Some((ptr, layout)),
+        }
+    }
+
+    (cpu::read_timestamp_counter() - start, failed_count)
+}
+
+/// A xorshift generator, the same seed gives the same allocations on every run
+#[inline(always)]
+fn get_random(state: &mut u64) -> usize {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+
+    *state as usize
+}
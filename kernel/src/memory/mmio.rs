@@ -0,0 +1,104 @@
+use core::ptr::NonNull;
+
+use crate::{
+    allocators::buddy_allocator::Frame,
+    paging::{CacheType, MIN_PAGE_SIZE},
+};
+
+use super::{RegionFlags, align_down, vfree, vmap};
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// A value that a device register can be read as or written as in a single access
+pub trait MmioValue: private::Sealed + Copy {}
+
+macro_rules! impl_mmio_value {
+    ($($ty:ty),*) => {
+        $(
+            impl private::Sealed for $ty {}
+            impl MmioValue for $ty {}
+        )*
+    };
+}
+
+impl_mmio_value!(u8, u16, u32, u64);
+
+/// The registers of a device that are mapped by `ioremap`, every access is checked against the
+/// size of the block, so a wrong offset panics instead of touching another device
+#[derive(Debug)]
+pub struct Mmio {
+    base: NonNull<u8>,
+    len: usize,
+}
+
+unsafe impl Send for Mmio {}
+unsafe impl Sync for Mmio {}
+
+impl Mmio {
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline(always)]
+    fn get_register<T: MmioValue>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset
+                .checked_add(size_of::<T>())
+                .is_some_and(|end| end <= self.len),
+            "register at offset {offset:#x} is outside of a {:#x} bytes register block",
+            self.len
+        );
+
+        assert!(
+            offset.is_multiple_of(align_of::<T>()),
+            "register at offset {offset:#x} is not aligned"
+        );
+
+        unsafe { self.base.add(offset).cast::<T>().as_ptr() }
+    }
+
+    pub fn read<T: MmioValue>(&self, offset: usize) -> T {
+        unsafe { self.get_register::<T>(offset).read_volatile() }
+    }
+
+    pub fn write<T: MmioValue>(&self, offset: usize, value: T) {
+        unsafe { self.get_register::<T>(offset).write_volatile(value) }
+    }
+}
+
+/// Maps `len` bytes of device memory at `phys` into the VMM with the memory type `cache_type`,
+/// returns `None` if there are not enough virtual addresses or memory for page tables
+pub fn ioremap(phys: usize, len: usize, cache_type: CacheType, name: &'static str) -> Option<Mmio> {
+    let start = align_down(phys, MIN_PAGE_SIZE);
+    let end = phys.checked_add(len)?.next_multiple_of(MIN_PAGE_SIZE);
+
+    let frames = (start..end).step_by(MIN_PAGE_SIZE).map(Frame::from_phys);
+
+    let flags = RegionFlags {
+        is_writable: true,
+        is_executable: false,
+        cache_type,
+    };
+
+    let virt = vmap(frames, name, flags)?;
+
+    Some(Mmio {
+        base: unsafe { virt.add(phys - start) },
+        len,
+    })
+}
+
+/// Unmaps registers that were mapped by `ioremap`
+pub fn iounmap(mmio: Mmio) {
+    let offset = mmio.base.addr().get() % MIN_PAGE_SIZE;
+
+    vfree(unsafe { mmio.base.sub(offset) });
+}
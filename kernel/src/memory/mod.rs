@@ -0,0 +1,1022 @@
+mod address_space;
+#[cfg(feature = "benchmarks")]
+mod benchmarks;
+mod mmio;
+mod oom;
+mod page_fault;
+#[cfg(feature = "self_checks")]
+mod self_checks;
+mod stats;
+#[cfg(feature = "alloc_tracking")]
+mod tracking;
+mod vmm;
+
+use alloc::vec::Vec;
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+};
+
+use limine::memory_map::EntryType as MemoryEntryType;
+use spin::{Lazy, Mutex, MutexGuard};
+
+use crate::{
+    allocators::{
+        buddy_allocator::{self, BuddyAllocator, Frame, FrameInfo, MAX_ORDER},
+        bump_allocator::BumpAllocator,
+        magazine::{MAGAZINE_BATCH_SIZE, Magazine},
+        page_allocator::PageAllocator,
+        slab_allocator::{self, MAX_SLAB_OBJECT_SIZE, SIZE_CLASSES, SlabCache},
+    },
+    arch::{
+        cpu, interrupts,
+        tlb::{ALL_CPUS, TlbShootdown},
+    },
+    cmdline,
+    mp::MAX_CPU_COUNT,
+    paging::{self, MIN_PAGE_SIZE, MapError},
+    requests::MEMORY_MAP_REQUEST,
+    screen,
+};
+
+pub use address_space::AddressSpace;
+#[cfg(feature = "benchmarks")]
+pub use benchmarks::run_benchmarks;
+pub use mmio::{Mmio, MmioValue, ioremap, iounmap};
+pub use oom::enable_reserve_pool;
+pub use page_fault::{PageFaultError, find_overflowed_stack, handle_page_fault};
+#[cfg(feature = "self_checks")]
+pub use self_checks::run_self_checks;
+pub use stats::{
+    MEMINFO_FLAG, MemoryStats, RegionStats, SlabStats, ZoneStats, print_stats, stats,
+    write_stats_without_waiting,
+};
+#[cfg(feature = "alloc_tracking")]
+pub use tracking::{ALLOCATIONS_FLAG, dump_allocations, get_tracked_allocation_count};
+pub use vmm::{
+    Region, RegionFlags, VMM, VMM_END, VMM_START, Vmm, alloc_stack, vfree, vmalloc, vmap,
+};
+
+const MAX_ARENA_COUNT: usize = 128;
+
+/// Heap arenas are at least 2^ARENA_ORDER pages, which is 4 MiB
+const ARENA_ORDER: usize = 10;
+
+/// Allocations aligned to more than this, which is 2 MiB, do not fit well in arenas, so they
+/// are served by whole blocks of `FRAME_ALLOCATOR`, which are aligned to their size
+const MAX_ARENA_ALIGNMENT: usize = MIN_PAGE_SIZE << (ARENA_ORDER - 1);
+
+/// Bytes that can be allocated before `init`, which is plenty for the copies of the memory map
+/// and the command line
+const EARLY_ALLOCATOR_SIZE: usize = 64 * 1024;
+
+/// Serves every allocation until `init` processed the memory map, nothing allocated from it is
+/// ever freed
+static EARLY_ALLOCATOR: BumpAllocator<EARLY_ALLOCATOR_SIZE> = BumpAllocator::new();
+
+/// The stages memory management goes through while booting, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Phase {
+    /// Allocations are served by the early allocator
+    Early,
+    /// `init` is processing the memory map, allocations are still served by the early allocator
+    Initializing,
+    /// Allocations are served by the heap
+    Ready,
+}
+
+static PHASE: AtomicU8 = AtomicU8::new(Phase::Early as u8);
+
+#[inline(always)]
+pub fn get_phase() -> Phase {
+    match PHASE.load(Ordering::Acquire) {
+        0 => Phase::Early,
+        1 => Phase::Initializing,
+        _ => Phase::Ready,
+    }
+}
+
+#[inline(always)]
+fn set_phase(phase: Phase) {
+    PHASE.store(phase as u8, Ordering::Release);
+}
+
+/// Processes the memory map and switches allocations from the early allocator to the heap, it
+/// must be called once on the bootstrap processor, before anything needs frames
+pub fn init() {
+    assert_eq!(get_phase(), Phase::Early, "memory is already initialized");
+
+    // Otherwise an interrupt handler could allocate while we are halfway through
+    assert!(
+        !interrupts::are_enabled(),
+        "memory must be initialized with interrupts disabled"
+    );
+
+    set_phase(Phase::Initializing);
+
+    Lazy::force(&MEMORY_MAP);
+    Lazy::force(&FRAME_ALLOCATOR);
+
+    set_phase(Phase::Ready);
+}
+
+/// Physical memory is split in zones for devices that can only address low memory, every
+/// allocation prefers the highest zone it is allowed to use, so low memory is kept for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    /// Below 16 MiB, for ISA DMA
+    Dma,
+    /// Below 4 GiB, for devices with 32 bit addressing
+    Dma32,
+    Normal,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 3] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Zone::Dma => "dma",
+            Zone::Dma32 => "dma32",
+            Zone::Normal => "normal",
+        }
+    }
+
+    /// The first frame that is past the zone
+    pub const fn end(self) -> Frame {
+        match self {
+            Zone::Dma => Frame::from_phys(16 * 1024 * 1024),
+            Zone::Dma32 => Frame::from_phys(4 * 1024 * 1024 * 1024),
+            Zone::Normal => Frame(usize::MAX),
+        }
+    }
+
+    pub const fn start(self) -> Frame {
+        match self {
+            Zone::Dma => Frame(0),
+            Zone::Dma32 => Zone::Dma.end(),
+            Zone::Normal => Zone::Dma32.end(),
+        }
+    }
+
+    pub fn of(frame: Frame) -> Zone {
+        Zone::ALL
+            .into_iter()
+            .find(|zone| frame < zone.end())
+            .unwrap_or(Zone::Normal)
+    }
+}
+
+/// A buddy allocator for each zone, they never merge blocks with each other
+pub struct FrameAllocator {
+    zones: [Mutex<BuddyAllocator>; Zone::ALL.len()],
+}
+
+impl FrameAllocator {
+    /// Allocates 2^order frames from `highest_zone` or any zone below it
+    pub fn alloc(&self, order: usize, highest_zone: Zone) -> Option<Frame> {
+        Zone::ALL[..=highest_zone as usize]
+            .iter()
+            .rev()
+            .find_map(|&zone| self.zones[zone as usize].lock().alloc(order))
+    }
+
+    /// Gives a range of frames that were never given before to the zones they are in
+    pub fn add_range(&self, start: Frame, end: Frame) {
+        for zone in self.zones.iter() {
+            zone.lock().add_range(start, end);
+        }
+    }
+
+    /// Frees a block once every owner of it let go of it
+    pub fn free(&self, frame: Frame, order: usize) {
+        let mut zone = self.zones[Zone::of(frame) as usize].lock();
+
+        if zone.drop_ref(frame) {
+            zone.free(frame, order);
+        }
+    }
+
+    pub fn add_ref(&self, frame: Frame) {
+        self.zones[Zone::of(frame) as usize].lock().add_ref(frame);
+    }
+
+    pub fn get_ref_count(&self, frame: Frame) -> usize {
+        self.zones[Zone::of(frame) as usize]
+            .lock()
+            .get_ref_count(frame)
+    }
+
+    #[inline(always)]
+    pub fn get_zone(&self, zone: Zone) -> MutexGuard<'_, BuddyAllocator> {
+        self.zones[zone as usize].lock()
+    }
+
+    pub fn free_frame_count(&self) -> usize {
+        self.zones
+            .iter()
+            .map(|zone| zone.lock().free_frame_count())
+            .sum()
+    }
+
+    /// Bytes in the biggest free block of any zone
+    pub fn find_largest_free_block(&self) -> usize {
+        Zone::ALL
+            .into_iter()
+            .filter_map(|zone| self.get_zone(zone).largest_free_order())
+            .map(|order| MIN_PAGE_SIZE << order)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn total_frame_count(&self) -> usize {
+        self.zones
+            .iter()
+            .map(|zone| zone.lock().total_frame_count())
+            .sum()
+    }
+}
+
+/// Every physical frame that is not reserved by the firmware, the bootloader or the kernel
+pub static FRAME_ALLOCATOR: Lazy<FrameAllocator> = Lazy::new(|| {
+    assert_eq!(
+        get_phase(),
+        Phase::Initializing,
+        "frames are needed before memory is initialized"
+    );
+
+    let entries = MEMORY_MAP_REQUEST
+        .get_response()
+        .expect("could not ask limine to get the memory map")
+        .entries();
+
+    let is_usable = |entry_type| entry_type == MemoryEntryType::USABLE;
+
+    // Reclaimable memory is given to us later, but it must be described from the start
+    let frame_count = entries
+        .iter()
+        .filter(|entry| is_usable(entry.entry_type) || is_reclaimable(entry.entry_type))
+        .map(|entry| Frame::from_phys((entry.base + entry.length) as usize).0)
+        .max()
+        .expect("no usable memory");
+
+    let frames_len = (frame_count * size_of::<FrameInfo>()).div_ceil(MIN_PAGE_SIZE) * MIN_PAGE_SIZE;
+
+    // The frame information lives at the start of the first region that can hold it
+    let frames_region = entries
+        .iter()
+        .find(|entry| is_usable(entry.entry_type) && entry.length as usize >= frames_len)
+        .expect("not enough contiguous memory to hold the frame information");
+
+    let mut frames = unsafe {
+        core::slice::from_raw_parts_mut(
+            paging::offset(frames_region.base as usize) as *mut FrameInfo,
+            frame_count,
+        )
+    };
+
+    // Each zone gets the part of the frame information that describes its own frames
+    let zones = Zone::ALL.map(|zone| {
+        let zone_len = zone.end().0.min(frame_count).saturating_sub(zone.start().0);
+        let (zone_frames, rest) = core::mem::take(&mut frames).split_at_mut(zone_len);
+
+        frames = rest;
+
+        let mut buddy_allocator = BuddyAllocator::new(zone.start(), zone_frames);
+
+        for entry in entries.iter().filter(|entry| is_usable(entry.entry_type)) {
+            let mut start = entry.base as usize;
+            let end = (entry.base + entry.length) as usize;
+
+            if entry.base == frames_region.base {
+                start += frames_len;
+            }
+
+            buddy_allocator.add_range(
+                Frame::from_phys(align_up(start, MIN_PAGE_SIZE)),
+                Frame::from_phys(align_down(end, MIN_PAGE_SIZE)),
+            );
+        }
+
+        Mutex::new(buddy_allocator)
+    });
+
+    FrameAllocator { zones }
+});
+
+#[inline(always)]
+fn is_reclaimable(entry_type: MemoryEntryType) -> bool {
+    entry_type == MemoryEntryType::BOOTLOADER_RECLAIMABLE
+        || entry_type == MemoryEntryType::ACPI_RECLAIMABLE
+}
+
+/// A region of limine's memory map
+#[derive(Clone, Copy)]
+pub struct MemoryRegion {
+    pub base: usize,
+    pub length: usize,
+    pub entry_type: MemoryEntryType,
+}
+
+/// A copy of limine's memory map, since the original lives in memory that is reclaimed
+pub static MEMORY_MAP: Lazy<Vec<MemoryRegion>> = Lazy::new(|| {
+    MEMORY_MAP_REQUEST
+        .get_response()
+        .expect("could not ask limine to get the memory map")
+        .entries()
+        .iter()
+        .map(|entry| MemoryRegion {
+            base: entry.base as usize,
+            length: entry.length as usize,
+            entry_type: entry.entry_type,
+        })
+        .collect()
+});
+
+static IS_BOOTLOADER_MEMORY_RECLAIMED: AtomicBool = AtomicBool::new(false);
+static IS_ACPI_MEMORY_RECLAIMED: AtomicBool = AtomicBool::new(false);
+
+/// Gives every region of `entry_type` to `FRAME_ALLOCATOR`, and prints each region that was
+/// reclaimed
+fn reclaim(name: &str, entry_type: MemoryEntryType) {
+    let mut reclaimed_frame_count = 0;
+
+    for region in MEMORY_MAP
+        .iter()
+        .filter(|region| region.entry_type == entry_type)
+    {
+        let start = Frame::from_phys(align_up(region.base, MIN_PAGE_SIZE));
+        let end = Frame::from_phys(align_down(region.base + region.length, MIN_PAGE_SIZE));
+
+        FRAME_ALLOCATOR.add_range(start, end);
+
+        reclaimed_frame_count += end.0.saturating_sub(start.0);
+
+        println!(
+            "memory: reclaimed {name} region {:#x}-{:#x}",
+            region.base,
+            region.base + region.length
+        );
+    }
+
+    println!(
+        "memory: reclaimed {} KiB of {name} memory",
+        reclaimed_frame_count * MIN_PAGE_SIZE / 1024
+    );
+}
+
+/// Hands the memory that the bootloader used to `FRAME_ALLOCATOR`, limine's responses must not
+/// be used after this, and the application processors must be booted already, so every CPU is
+/// on the kernel's page table and on its kernel stack
+pub fn reclaim_bootloader_memory() {
+    assert!(
+        !IS_BOOTLOADER_MEMORY_RECLAIMED.swap(true, Ordering::Relaxed),
+        "bootloader memory is already reclaimed"
+    );
+
+    // Everything that is still needed from limine's responses is copied out first
+    Lazy::force(&MEMORY_MAP);
+    screen::is_available();
+    cmdline::get();
+
+    reclaim("bootloader", MemoryEntryType::BOOTLOADER_RECLAIMABLE);
+}
+
+/// Hands the memory that holds the ACPI tables to `FRAME_ALLOCATOR`, `acpi::ACPI` must not be
+/// used after this
+pub fn reclaim_acpi_memory() {
+    assert!(
+        !IS_ACPI_MEMORY_RECLAIMED.swap(true, Ordering::Relaxed),
+        "ACPI memory is already reclaimed"
+    );
+
+    Lazy::force(&MEMORY_MAP);
+
+    reclaim("ACPI", MemoryEntryType::ACPI_RECLAIMABLE);
+}
+
+/// Allocates 2^order contiguous physical frames, aligned to their size
+pub fn alloc_frames(order: usize) -> Option<Frame> {
+    FRAME_ALLOCATOR.alloc(order, Zone::Normal)
+}
+
+/// Allocates 2^order contiguous physical frames that are all in `highest_zone` or below it,
+/// meant for devices that can not address all of physical memory
+pub fn alloc_frames_in(order: usize, highest_zone: Zone) -> Option<Frame> {
+    FRAME_ALLOCATOR.alloc(order, highest_zone)
+}
+
+pub fn alloc_frame() -> Option<Frame> {
+    alloc_frames(0)
+}
+
+/// Frees frames returned by `alloc_frames` or `alloc_frames_in` with the same order
+pub fn free_frames(frame: Frame, order: usize) {
+    FRAME_ALLOCATOR.free(frame, order);
+}
+
+pub fn free_frame(frame: Frame) {
+    free_frames(frame, 0);
+}
+
+/// Adds an owner to frames returned by `alloc_frames` or `alloc_frames_in`, they are only freed
+/// once every owner freed them
+pub fn share_frames(frame: Frame) {
+    FRAME_ALLOCATOR.add_ref(frame);
+}
+
+/// Amount of owners of frames returned by `alloc_frames` or `alloc_frames_in`
+pub fn get_frame_ref_count(frame: Frame) -> usize {
+    FRAME_ALLOCATOR.get_ref_count(frame)
+}
+
+/// Page granular allocations made from arenas of contiguous frames, each with its own bitmap,
+/// new arenas are taken from `FRAME_ALLOCATOR` whenever the existing ones are exhausted
+#[repr(transparent)]
+pub struct ChainedPageAllocators(Mutex<[Option<PageAllocator>; MAX_ARENA_COUNT]>);
+
+impl ChainedPageAllocators {
+    pub const fn new() -> ChainedPageAllocators {
+        ChainedPageAllocators(Mutex::new([const { None }; MAX_ARENA_COUNT]))
+    }
+
+    pub fn calculate_free_space(&self) -> usize {
+        self.0
+            .lock()
+            .iter()
+            .flatten()
+            .map(|a| a.calculate_free_space())
+            .sum()
+    }
+
+    pub fn calculate_total_space(&self) -> usize {
+        self.0
+            .lock()
+            .iter()
+            .flatten()
+            .map(|a| a.page_count * MIN_PAGE_SIZE)
+            .sum()
+    }
+
+    /// Bytes in the longest run of free pages of any arena
+    pub fn find_largest_free_run(&self) -> usize {
+        self.0
+            .lock()
+            .iter()
+            .flatten()
+            .map(|a| a.find_largest_free_run() * MIN_PAGE_SIZE)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn arena_count(&self) -> usize {
+        self.0.lock().iter().flatten().count()
+    }
+
+    pub fn contains(&self, address: usize) -> bool {
+        self.0.lock().iter().flatten().any(|a| a.contains(address))
+    }
+
+    /// Takes a new arena from `FRAME_ALLOCATOR` that can hold at least `layout`
+    fn grow(
+        page_allocators: &mut [Option<PageAllocator>],
+        layout: Layout,
+    ) -> Option<&mut PageAllocator> {
+        let slot = page_allocators.iter_mut().find(|a| a.is_none())?;
+
+        let page_count = layout.size().div_ceil(MIN_PAGE_SIZE);
+        let align_page_count = (layout.align() / MIN_PAGE_SIZE).max(1);
+
+        // Arenas are aligned to their size, so the first aligned page is the first one after the
+        // bitmap that is a multiple of the alignment
+        let order = (ARENA_ORDER..=MAX_ORDER).find(|&order| {
+            let first_page_index =
+                PageAllocator::get_bitmap_page_count(1 << order).next_multiple_of(align_page_count);
+
+            first_page_index + page_count <= 1 << order
+        })?;
+
+        let arena = alloc_frames(order)?;
+
+        let page_allocator = PageAllocator::new(
+            unsafe { NonNull::new_unchecked(arena.virt() as *mut u8) },
+            MIN_PAGE_SIZE << order,
+        );
+
+        Some(slot.insert(page_allocator))
+    }
+
+    /// Allocates from arenas that are already locked, which lets a batch of allocations be done
+    /// with a single lock, `layout` must not be served by frames
+    fn alloc_in(page_allocators: &mut [Option<PageAllocator>], layout: Layout) -> *mut u8 {
+        let ptr = page_allocators
+            .iter_mut()
+            .flatten()
+            .map(|a| a.alloc(layout))
+            .find(|ptr| !ptr.is_null());
+
+        match ptr {
+            Some(ptr) => ptr,
+
+            None => Self::grow(page_allocators, layout)
+                .map_or(core::ptr::null_mut(), |a| a.alloc(layout)),
+        }
+    }
+
+    /// Deallocates from arenas that are already locked, `layout` must not be served by frames,
+    /// returns `false` if no arena owns `ptr`, the caller should panic once the lock is dropped
+    #[must_use]
+    fn dealloc_in(
+        page_allocators: &mut [Option<PageAllocator>],
+        ptr: *mut u8,
+        layout: Layout,
+    ) -> bool {
+        match page_allocators
+            .iter_mut()
+            .flatten()
+            .find(|a| a.contains(ptr.addr()))
+        {
+            Some(allocator) => {
+                allocator.dealloc(ptr, layout);
+                true
+            }
+
+            None => false,
+        }
+    }
+
+    #[inline(always)]
+    fn is_served_by_frames(layout: Layout) -> bool {
+        layout.align() > MAX_ARENA_ALIGNMENT
+    }
+
+    #[inline(always)]
+    fn get_frame_order_of(size: usize, align: usize) -> usize {
+        buddy_allocator::get_order_of(size.max(align).div_ceil(MIN_PAGE_SIZE))
+    }
+}
+
+impl Default for ChainedPageAllocators {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl Send for ChainedPageAllocators {}
+unsafe impl Sync for ChainedPageAllocators {}
+
+unsafe impl GlobalAlloc for ChainedPageAllocators {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if Self::is_served_by_frames(layout) {
+            let order = Self::get_frame_order_of(layout.size(), layout.align());
+
+            return alloc_frames(order)
+                .map_or(core::ptr::null_mut(), |frame| frame.virt() as *mut u8);
+        }
+
+        Self::alloc_in(&mut *self.0.lock(), layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if Self::is_served_by_frames(layout) {
+            let frame = Frame::from_phys(ptr.addr() - paging::offset(0));
+            let order = Self::get_frame_order_of(layout.size(), layout.align());
+
+            return free_frames(frame, order);
+        }
+
+        let is_owned = Self::dealloc_in(&mut *self.0.lock(), ptr, layout);
+
+        assert!(
+            is_owned,
+            "freeing {ptr:p}, which is not owned by any heap arena"
+        );
+    }
+
+    unsafe fn realloc(&self, old_ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let can_resize = if Self::is_served_by_frames(layout) {
+            Self::get_frame_order_of(layout.size(), layout.align())
+                == Self::get_frame_order_of(new_size, layout.align())
+        } else {
+            let mut page_allocators = self.0.lock();
+
+            let Some(allocator) = page_allocators
+                .iter_mut()
+                .flatten()
+                .find(|a| a.contains(old_ptr.addr()))
+            else {
+                drop(page_allocators);
+
+                panic!("reallocating {old_ptr:p}, which is not owned by any heap arena");
+            };
+
+            allocator.resize(old_ptr, layout, new_size)
+        };
+
+        if can_resize {
+            return old_ptr;
+        }
+
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+
+        let new_ptr = unsafe { self.alloc(new_layout) };
+
+        if !new_ptr.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(old_ptr, new_ptr, layout.size().min(new_size));
+
+                self.dealloc(old_ptr, layout);
+            }
+        }
+
+        new_ptr
+    }
+}
+
+pub static PAGE_ALLOCATOR: ChainedPageAllocators = ChainedPageAllocators::new();
+
+/// Whether `address` is in a heap arena, or in a frame that the frame allocator manages, which
+/// is where allocations that are served by frames come from, or in the static memory that is
+/// used before memory is initialized and once we are out of memory
+pub fn is_owned_by_heap(address: usize) -> bool {
+    if Heap::is_never_freed(address as *mut u8) {
+        return true;
+    }
+
+    if get_phase() != Phase::Ready {
+        return false;
+    }
+
+    let is_in_frame_allocator = address
+        .checked_sub(paging::offset(0))
+        .map(Frame::from_phys)
+        .is_some_and(|frame| {
+            Zone::ALL
+                .into_iter()
+                .any(|zone| FRAME_ALLOCATOR.get_zone(zone).contains(frame))
+        });
+
+    is_in_frame_allocator || PAGE_ALLOCATOR.contains(address)
+}
+
+/// Free slab objects and pages owned by a single CPU
+struct CpuCache {
+    slab_magazines: [Magazine; SIZE_CLASSES.len()],
+    /// Single pages, for allocations that are too big for slabs
+    page_magazine: Magazine,
+}
+
+impl CpuCache {
+    const fn new() -> CpuCache {
+        CpuCache {
+            slab_magazines: [const { Magazine::new() }; SIZE_CLASSES.len()],
+            page_magazine: Magazine::new(),
+        }
+    }
+}
+
+/// The kernel heap, small objects are served by slab caches which take their slabs from
+/// `PAGE_ALLOCATOR`, and everything else is served by `PAGE_ALLOCATOR` directly, objects and
+/// single pages go through a per CPU magazine first, so CPUs rarely contend on the global locks
+pub struct Heap {
+    slab_caches: [Mutex<SlabCache>; SIZE_CLASSES.len()],
+    cpu_caches: [Mutex<CpuCache>; MAX_CPU_COUNT],
+}
+
+impl Heap {
+    pub const fn new() -> Heap {
+        let mut slab_caches = [const { Mutex::new(SlabCache::new(0)) }; SIZE_CLASSES.len()];
+
+        let mut i = 0;
+
+        while i < SIZE_CLASSES.len() {
+            slab_caches[i] = Mutex::new(SlabCache::new(SIZE_CLASSES[i]));
+            i += 1;
+        }
+
+        Heap {
+            slab_caches,
+            cpu_caches: [const { Mutex::new(CpuCache::new()) }; MAX_CPU_COUNT],
+        }
+    }
+
+    /// Get the statistics of each slab cache, objects that sit in the magazines of CPUs are
+    /// counted as used
+    pub fn slab_stats(&self) -> [SlabStats; SIZE_CLASSES.len()] {
+        core::array::from_fn(|i| {
+            let slab_cache = self.slab_caches[i].lock();
+
+            SlabStats {
+                object_size: slab_cache.object_size(),
+                slab_count: slab_cache.slab_count,
+                used_count: slab_cache.used_count,
+            }
+        })
+    }
+
+    /// The cache of the CPU we are running on, `None` before the CPU is set up, or if an
+    /// interrupt came while the cache was in use, in which case the global allocators are used
+    #[inline(always)]
+    fn get_cpu_cache(&self) -> Option<MutexGuard<'_, CpuCache>> {
+        self.cpu_caches.get(cpu::get_current_id()?)?.try_lock()
+    }
+
+    #[inline(always)]
+    fn is_single_page(layout: Layout) -> bool {
+        layout.size() > MAX_SLAB_OBJECT_SIZE
+            && layout.size() <= MIN_PAGE_SIZE
+            && layout.align() <= MIN_PAGE_SIZE
+    }
+
+    #[inline(always)]
+    fn single_page_layout() -> Layout {
+        unsafe { Layout::from_size_align_unchecked(MIN_PAGE_SIZE, MIN_PAGE_SIZE) }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    unsafe fn alloc_untracked(&self, layout: Layout) -> *mut u8 {
+        // The heap takes its memory from `FRAME_ALLOCATOR`, which is only set up by `init`
+        if get_phase() != Phase::Ready {
+            return EARLY_ALLOCATOR.alloc(layout);
+        }
+
+        let size_class_index = slab_allocator::get_size_class_index(layout);
+
+        match (size_class_index, self.get_cpu_cache()) {
+            (Some(index), Some(mut cpu_cache)) => {
+                let magazine = &mut cpu_cache.slab_magazines[index];
+
+                if magazine.is_empty() {
+                    let mut slab_cache = self.slab_caches[index].lock();
+
+                    magazine.refill(|| slab_cache.alloc(&PAGE_ALLOCATOR));
+                }
+
+                magazine.pop().unwrap_or(core::ptr::null_mut())
+            }
+
+            (Some(index), None) => self.slab_caches[index].lock().alloc(&PAGE_ALLOCATOR),
+
+            (None, Some(mut cpu_cache)) if Self::is_single_page(layout) => {
+                let magazine = &mut cpu_cache.page_magazine;
+
+                if magazine.is_empty() {
+                    let mut page_allocators = PAGE_ALLOCATOR.0.lock();
+
+                    magazine.refill(|| {
+                        ChainedPageAllocators::alloc_in(
+                            &mut *page_allocators,
+                            Self::single_page_layout(),
+                        )
+                    });
+                }
+
+                magazine.pop().unwrap_or(core::ptr::null_mut())
+            }
+
+            (None, _) => unsafe { PAGE_ALLOCATOR.alloc(layout) },
+        }
+    }
+
+    unsafe fn dealloc_untracked(&self, ptr: *mut u8, layout: Layout) {
+        let size_class_index = slab_allocator::get_size_class_index(layout);
+
+        match (size_class_index, self.get_cpu_cache()) {
+            (Some(index), Some(mut cpu_cache)) => {
+                let magazine = &mut cpu_cache.slab_magazines[index];
+
+                if magazine.is_full() {
+                    let mut slab_cache = self.slab_caches[index].lock();
+
+                    magazine.drain(MAGAZINE_BATCH_SIZE, |object| unsafe {
+                        slab_cache.dealloc(object, &PAGE_ALLOCATOR)
+                    });
+                }
+
+                magazine.push(ptr);
+            }
+
+            (Some(index), None) => unsafe {
+                self.slab_caches[index].lock().dealloc(ptr, &PAGE_ALLOCATOR)
+            },
+
+            (None, Some(mut cpu_cache)) if Self::is_single_page(layout) => {
+                let magazine = &mut cpu_cache.page_magazine;
+
+                if magazine.is_full() {
+                    let mut page_allocators = PAGE_ALLOCATOR.0.lock();
+                    let mut are_owned = true;
+
+                    magazine.drain(MAGAZINE_BATCH_SIZE, |page| {
+                        are_owned &= ChainedPageAllocators::dealloc_in(
+                            &mut *page_allocators,
+                            page,
+                            Self::single_page_layout(),
+                        )
+                    });
+
+                    drop(page_allocators);
+
+                    assert!(
+                        are_owned,
+                        "freed a page that is not owned by any heap arena"
+                    );
+                }
+
+                magazine.push(ptr);
+            }
+
+            (None, _) => unsafe { PAGE_ALLOCATOR.dealloc(ptr, layout) },
+        }
+    }
+
+    unsafe fn realloc_untracked(
+        &self,
+        old_ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+
+        match (
+            slab_allocator::get_size_class_index(layout),
+            slab_allocator::get_size_class_index(new_layout),
+        ) {
+            (None, None) => unsafe { PAGE_ALLOCATOR.realloc(old_ptr, layout, new_size) },
+
+            // The object already has room for the new size
+            (Some(old_index), Some(new_index)) if old_index == new_index => old_ptr,
+
+            _ => unsafe {
+                let new_ptr = self.alloc_untracked(new_layout);
+
+                if !new_ptr.is_null() {
+                    core::ptr::copy_nonoverlapping(old_ptr, new_ptr, layout.size().min(new_size));
+
+                    self.dealloc_untracked(old_ptr, layout);
+                }
+
+                new_ptr
+            },
+        }
+    }
+}
+
+impl Heap {
+    /// Whether `ptr` was allocated by the early allocator or the reserve pool, which never free
+    #[inline(always)]
+    fn is_never_freed(ptr: *mut u8) -> bool {
+        EARLY_ALLOCATOR.contains(ptr) || oom::is_in_reserve_pool(ptr)
+    }
+
+    /// Falls back to the reserve pool, which only gives memory once it is enabled
+    unsafe fn alloc_untracked_or_reserve(&self, layout: Layout) -> *mut u8 {
+        match unsafe { self.alloc_untracked(layout) } {
+            ptr if ptr.is_null() => oom::alloc_from_reserve_pool(layout),
+            ptr => ptr,
+        }
+    }
+
+    /// Moves an allocation into a new one, the old allocation is kept if this fails
+    unsafe fn move_untracked(
+        &self,
+        old_ptr: *mut u8,
+        layout: Layout,
+        new_layout: Layout,
+    ) -> *mut u8 {
+        let new_ptr = unsafe { self.alloc_untracked_or_reserve(new_layout) };
+
+        if !new_ptr.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    old_ptr,
+                    new_ptr,
+                    layout.size().min(new_layout.size()),
+                );
+            }
+
+            if !Self::is_never_freed(old_ptr) {
+                unsafe { self.dealloc_untracked(old_ptr, layout) };
+            }
+        }
+
+        new_ptr
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.alloc_untracked_or_reserve(layout) };
+
+        #[cfg(feature = "alloc_tracking")]
+        tracking::track_alloc(ptr, layout);
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "alloc_tracking")]
+        tracking::track_dealloc(ptr, layout);
+
+        if Self::is_never_freed(ptr) {
+            return;
+        }
+
+        unsafe { self.dealloc_untracked(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, old_ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        #[cfg(feature = "alloc_tracking")]
+        tracking::track_dealloc(old_ptr, layout);
+
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+
+        // Allocations that are never freed can not be resized either, and allocations that can
+        // not be reallocated may still fit in the reserve pool, in both cases they are moved
+        let mut new_ptr = if Self::is_never_freed(old_ptr) {
+            core::ptr::null_mut()
+        } else {
+            unsafe { self.realloc_untracked(old_ptr, layout, new_size) }
+        };
+
+        if new_ptr.is_null() {
+            new_ptr = unsafe { self.move_untracked(old_ptr, layout, new_layout) };
+        }
+
+        // The old allocation is still alive if reallocating failed
+        #[cfg(feature = "alloc_tracking")]
+        if new_ptr.is_null() {
+            tracking::track_alloc(old_ptr, layout);
+        } else {
+            tracking::track_alloc(new_ptr, new_layout);
+        }
+
+        new_ptr
+    }
+}
+
+#[global_allocator]
+pub static HEAP: Heap = Heap::new();
+
+pub fn align_up(address: usize, alignment: usize) -> usize {
+    address.next_multiple_of(alignment)
+}
+
+pub fn align_down(address: usize, alignment: usize) -> usize {
+    address - (address % alignment)
+}
+
+pub fn unmap<T>(object: &T) {
+    let virt = object as *const _ as usize;
+
+    let mut aligned_virt = align_down(virt, MIN_PAGE_SIZE);
+    let aligned_virt_end = align_up(virt + size_of::<T>(), MIN_PAGE_SIZE);
+
+    let page_table = paging::get_active_table();
+
+    // The direct map is shared by every CPU
+    let mut shootdown = TlbShootdown::new(ALL_CPUS);
+
+    while aligned_virt < aligned_virt_end {
+        page_table.unmap(aligned_virt, &mut shootdown);
+
+        aligned_virt += MIN_PAGE_SIZE;
+    }
+}
+
+pub fn map<'a, T>(phys: usize) -> &'a T {
+    try_map(phys).expect("out of memory for page tables")
+}
+
+/// Like `map`, but fails instead of panicking if a page table can not be allocated, the pages
+/// that were mapped before failing stay mapped
+pub fn try_map<'a, T>(phys: usize) -> Result<&'a T, MapError> {
+    let mut aligned_phys = align_down(phys, MIN_PAGE_SIZE);
+
+    let virt = paging::offset(phys);
+
+    let mut aligned_virt = align_down(virt, MIN_PAGE_SIZE);
+    let aligned_virt_end = align_up(virt + size_of::<T>(), MIN_PAGE_SIZE);
+
+    let page_table = paging::get_active_table();
+    let mut shootdown = TlbShootdown::new(ALL_CPUS);
+
+    while aligned_virt < aligned_virt_end {
+        page_table.try_map(aligned_virt, aligned_phys, &mut shootdown)?;
+
+        aligned_virt += MIN_PAGE_SIZE;
+        aligned_phys += MIN_PAGE_SIZE;
+    }
+
+    Ok(unsafe { &*(virt as *const _) })
+}
+
@@ -0,0 +1,84 @@
+use core::{
+    alloc::Layout,
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    allocators::bump_allocator::BumpAllocator,
+    arch::serial::SERIAL,
+    console::{self, KERNEL_LOG_TERMINAL},
+};
+
+use super::{Phase, get_phase, write_stats_without_waiting};
+
+/// Bytes that are only handed out once we are out of memory or panicking, enough for the
+/// panic handler to create a console without scrollback and to print the memory statistics
+const RESERVE_POOL_SIZE: usize = 1024 * 1024;
+
+/// Nothing allocated from it is ever freed, which is fine because the kernel is going down
+/// once it is used
+static RESERVE_POOL: BumpAllocator<RESERVE_POOL_SIZE> = BumpAllocator::new();
+
+static IS_RESERVE_POOL_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Lets allocations that fail fall back to the reserve pool, this is done before panicking, so
+/// the panic handler itself does not run out of memory
+pub fn enable_reserve_pool() {
+    IS_RESERVE_POOL_ENABLED.store(true, Ordering::Release);
+}
+
+/// Allocates from the reserve pool, which returns null until it is enabled
+#[inline(always)]
+pub(super) fn alloc_from_reserve_pool(layout: Layout) -> *mut u8 {
+    if IS_RESERVE_POOL_ENABLED.load(Ordering::Acquire) {
+        RESERVE_POOL.alloc(layout)
+    } else {
+        core::ptr::null_mut()
+    }
+}
+
+#[inline(always)]
+pub(super) fn is_in_reserve_pool(ptr: *mut u8) -> bool {
+    RESERVE_POOL.contains(ptr)
+}
+
+#[alloc_error_handler]
+fn handle_out_of_memory(layout: Layout) -> ! {
+    enable_reserve_pool();
+
+    println!(
+        "out of memory while allocating {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+
+    let _ = writeln!(
+        SERIAL.lock(),
+        "out of memory while allocating {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+
+    // The statistics need the memory map, which is not processed yet in early boot, and they
+    // must not wait for the allocator that failed, which may still be locked by our caller
+    if get_phase() == Phase::Ready {
+        let mut console = console::get_terminal(KERNEL_LOG_TERMINAL);
+
+        let _ = write_stats_without_waiting(&mut *console);
+        let _ = writeln!(console);
+
+        drop(console);
+
+        let mut serial = SERIAL.lock();
+
+        let _ = write_stats_without_waiting(&mut *serial);
+        let _ = writeln!(serial);
+    }
+
+    panic!(
+        "out of memory while allocating {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+}
@@ -0,0 +1,69 @@
+use core::fmt;
+
+use crate::paging::{LOWER_HALF_END, PageFault};
+
+use super::{AddressSpace, VMM};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFaultError {
+    /// The address is in the guard page after the region with this name
+    Guard(&'static str),
+    /// The address is in the guard page below the stack with this name, so the stack overflowed
+    StackOverflow(&'static str),
+    /// Nothing is mapped at the address
+    NotMapped,
+    /// The page is mapped, but does not allow the access
+    NotAllowed,
+    /// There is no free frame to back the page
+    OutOfMemory,
+}
+
+impl fmt::Display for PageFaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageFaultError::Guard(name) => write!(f, "hit the guard page of {name}"),
+            PageFaultError::StackOverflow(name) => write!(f, "overflowed the {name}"),
+            PageFaultError::NotMapped => write!(f, "nothing is mapped there"),
+            PageFaultError::NotAllowed => write!(f, "the page does not allow it"),
+            PageFaultError::OutOfMemory => write!(f, "out of memory for backing the page"),
+        }
+    }
+}
+
+/// Resolves `fault` if it accessed a page that is allocated on its first access, otherwise
+/// returns why the access can not be done
+pub fn handle_page_fault(fault: &PageFault) -> Result<(), PageFaultError> {
+    if fault.address < LOWER_HALF_END {
+        return match AddressSpace::get_active() {
+            Some(address_space) => address_space.handle_page_fault(fault),
+            None => Err(PageFaultError::NotMapped),
+        };
+    }
+
+    if fault.was_present {
+        return Err(PageFaultError::NotAllowed);
+    }
+
+    // The fault may have happened while the VMM was locked, then we can only tell that nothing
+    // is mapped there
+    let guarded = VMM
+        .try_lock()
+        .and_then(|vmm| vmm.find_guarded(fault.address).copied());
+
+    match guarded {
+        Some(region) if region.is_in_stack_guard(fault.address) => {
+            Err(PageFaultError::StackOverflow(region.name))
+        }
+        Some(region) => Err(PageFaultError::Guard(region.name)),
+        None => Err(PageFaultError::NotMapped),
+    }
+}
+
+/// The name of the kernel stack whose guard page is at `address`, which is how a double fault
+/// that was caused by a stack overflow is told apart from other double faults
+pub fn find_overflowed_stack(address: usize) -> Option<&'static str> {
+    VMM.try_lock()?
+        .find(address)
+        .filter(|region| region.is_in_stack_guard(address))
+        .map(|region| region.name)
+}
@@ -0,0 +1,124 @@
+use alloc::alloc::{alloc, dealloc, realloc};
+use core::alloc::Layout;
+
+use crate::allocators::buddy_allocator::Frame;
+
+use super::{MAX_ARENA_ALIGNMENT, Zone, alloc_frames_in, free_frames};
+
+/// Alignments up to this, which is 1 GiB, are checked, it is the biggest block of frames
+const MAX_CHECKED_ALIGNMENT: usize = 1024 * 1024 * 1024;
+
+/// Sizes that cover slab objects, single pages, runs of pages and whole blocks of frames
+const CHECKED_SIZES: [usize; 8] = [8, 24, 200, 2000, 4096, 5000, 64 * 1024, 3 * 1024 * 1024];
+
+/// Checks that the heap keeps the `GlobalAlloc` contract and that frames come from the zones
+/// they were asked for, panics on the first check that fails
+pub fn run_self_checks() {
+    check_alignments();
+    check_zones();
+    check_realloc();
+
+    println!("memory: self checks passed");
+}
+
+/// Every allocation must be aligned to what its layout asks for, even to 1 GiB
+fn check_alignments() {
+    for align in (0..=MAX_CHECKED_ALIGNMENT.ilog2()).map(|shift| 1 << shift) {
+        for size in [1, align / 2 + 1, align] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+
+            let ptr = unsafe { alloc(layout) };
+
+            // There may not be a free block this big, which is allowed, as long as it is
+            // reported by returning null
+            if ptr.is_null() {
+                assert!(
+                    align > MAX_ARENA_ALIGNMENT,
+                    "could not allocate {size} bytes aligned to {align:#x}"
+                );
+
+                continue;
+            }
+
+            assert!(
+                ptr.addr().is_multiple_of(align),
+                "allocating {size} bytes aligned to {align:#x} returned {ptr:p}"
+            );
+
+            unsafe {
+                ptr.write_bytes(0xa5, size);
+
+                dealloc(ptr, layout);
+            }
+        }
+    }
+}
+
+/// Frames that are allocated for a zone must be entirely below the end of it
+fn check_zones() {
+    for zone in [Zone::Dma, Zone::Dma32] {
+        for order in [0, 4] {
+            let frame = alloc_frames_in(order, zone)
+                .unwrap_or_else(|| panic!("no free frames in the {zone:?} zone"));
+
+            assert!(
+                Frame(frame.0 + (1 << order)) <= zone.end(),
+                "frames allocated in the {zone:?} zone are at {:#x}, past its end",
+                frame.phys()
+            );
+
+            free_frames(frame, order);
+        }
+    }
+}
+
+/// Growing or shrinking an allocation must keep the bytes that fit in both sizes, whether it is
+/// resized in place or moved
+fn check_realloc() {
+    for align in [8, 4096, MAX_ARENA_ALIGNMENT * 2] {
+        for old_size in CHECKED_SIZES {
+            for new_size in CHECKED_SIZES.into_iter().filter(|&size| size != old_size) {
+                let layout = Layout::from_size_align(old_size, align).unwrap();
+
+                let ptr = unsafe { alloc(layout) };
+
+                assert!(
+                    !ptr.is_null(),
+                    "could not allocate {old_size} bytes aligned to {align:#x}"
+                );
+
+                for i in 0..old_size {
+                    unsafe { ptr.add(i).write(get_pattern_byte(i)) };
+                }
+
+                let new_ptr = unsafe { realloc(ptr, layout, new_size) };
+
+                assert!(
+                    !new_ptr.is_null(),
+                    "could not reallocate {old_size} bytes to {new_size} bytes"
+                );
+
+                assert!(
+                    new_ptr.addr().is_multiple_of(align),
+                    "reallocating to {new_size} bytes lost the alignment to {align:#x}"
+                );
+
+                for i in 0..old_size.min(new_size) {
+                    assert_eq!(
+                        unsafe { new_ptr.add(i).read() },
+                        get_pattern_byte(i),
+                        "reallocating {old_size} bytes to {new_size} bytes changed byte {i}"
+                    );
+                }
+
+                unsafe { dealloc(new_ptr, Layout::from_size_align_unchecked(new_size, align)) };
+            }
+        }
+    }
+}
+
+/// Does not repeat at any power of two, so bytes that are copied to the wrong offset are caught
+#[inline(always)]
+fn get_pattern_byte(index: usize) -> u8 {
+    (index % 251) as u8
+}
@@ -0,0 +1,449 @@
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use limine::memory_map::EntryType as MemoryEntryType;
+
+use crate::{
+    allocators::{buddy_allocator::Frame, slab_allocator::SIZE_CLASSES},
+    arch::{sections, serial::SERIAL},
+    paging::{self, MIN_PAGE_SIZE},
+};
+
+use super::{
+    EARLY_ALLOCATOR, FRAME_ALLOCATOR, HEAP, MEMORY_MAP, PAGE_ALLOCATOR, Region, VMM, Zone,
+};
+
+/// Passing this flag on the command line prints the memory statistics once the kernel booted
+pub const MEMINFO_FLAG: &str = "meminfo";
+
+const KIB: usize = 1024;
+
+const MEMORY_ENTRY_TYPES: [MemoryEntryType; 8] = [
+    MemoryEntryType::USABLE,
+    MemoryEntryType::RESERVED,
+    MemoryEntryType::ACPI_RECLAIMABLE,
+    MemoryEntryType::ACPI_NVS,
+    MemoryEntryType::BAD_MEMORY,
+    MemoryEntryType::BOOTLOADER_RECLAIMABLE,
+    MemoryEntryType::EXECUTABLE_AND_MODULES,
+    MemoryEntryType::FRAMEBUFFER,
+];
+
+fn get_entry_type_name(entry_type: MemoryEntryType) -> &'static str {
+    match entry_type {
+        MemoryEntryType::USABLE => "usable",
+        MemoryEntryType::RESERVED => "reserved",
+        MemoryEntryType::ACPI_RECLAIMABLE => "acpi reclaimable",
+        MemoryEntryType::ACPI_NVS => "acpi nvs",
+        MemoryEntryType::BAD_MEMORY => "bad memory",
+        MemoryEntryType::BOOTLOADER_RECLAIMABLE => "bootloader reclaimable",
+        MemoryEntryType::EXECUTABLE_AND_MODULES => "kernel and modules",
+        MemoryEntryType::FRAMEBUFFER => "framebuffer",
+        _ => "unknown",
+    }
+}
+
+/// A region of the memory map that is given to the frame allocator, or will be
+pub struct RegionStats {
+    pub base: usize,
+    pub length: usize,
+    pub entry_type: MemoryEntryType,
+    pub free_frame_count: usize,
+}
+
+impl RegionStats {
+    #[inline(always)]
+    pub fn frame_count(&self) -> usize {
+        self.length / MIN_PAGE_SIZE
+    }
+}
+
+pub struct ZoneStats {
+    pub zone: Zone,
+    pub free_frame_count: usize,
+    pub total_frame_count: usize,
+}
+
+pub struct SlabStats {
+    pub object_size: usize,
+    pub slab_count: usize,
+    pub used_count: usize,
+}
+
+pub struct MemoryStats {
+    /// Bytes of each type of memory in the memory map
+    pub bytes_per_type: [(MemoryEntryType, usize); MEMORY_ENTRY_TYPES.len()],
+    pub regions: Vec<RegionStats>,
+    pub zones: [ZoneStats; Zone::ALL.len()],
+    /// Bytes in the biggest free block of frames, the biggest that can be allocated at once
+    pub largest_free_block_bytes: usize,
+    pub arena_count: usize,
+    pub arena_bytes: usize,
+    pub arena_free_bytes: usize,
+    /// Bytes in the longest run of free pages of any arena, allocations bigger than it need a
+    /// new arena
+    pub largest_free_run_bytes: usize,
+    pub slabs: [SlabStats; SIZE_CLASSES.len()],
+    /// Regions of the kernel's virtual addresses that are handed out by the VMM
+    pub virtual_regions: Vec<Region>,
+    /// Bytes allocated before memory was initialized, which are never freed
+    pub early_bytes: usize,
+    /// Bytes used by the page tables of the active address space
+    pub page_table_bytes: usize,
+    pub kernel_text_bytes: usize,
+    pub kernel_rodata_bytes: usize,
+    pub kernel_data_bytes: usize,
+    pub kernel_bytes: usize,
+}
+
+/// Collects statistics about physical memory and every allocator, it takes every allocator
+/// lock in turn, so it must not be called while holding any of them
+pub fn stats() -> MemoryStats {
+    let mut free_blocks = Vec::new();
+
+    for zone in Zone::ALL {
+        FRAME_ALLOCATOR
+            .get_zone(zone)
+            .for_each_free_block(|frame, order| free_blocks.push((frame, order)));
+    }
+
+    let regions = MEMORY_MAP
+        .iter()
+        .filter(|region| {
+            region.entry_type == MemoryEntryType::USABLE
+                || region.entry_type == MemoryEntryType::BOOTLOADER_RECLAIMABLE
+                || region.entry_type == MemoryEntryType::ACPI_RECLAIMABLE
+        })
+        .map(|region| {
+            let start = Frame::from_phys(region.base);
+            let end = Frame::from_phys(region.base + region.length);
+
+            // Blocks of neighbouring regions can be merged, so only the overlap is counted
+            let free_frame_count = free_blocks
+                .iter()
+                .map(|&(frame, order)| {
+                    let block_end = frame.0 + (1 << order);
+
+                    block_end.min(end.0).saturating_sub(frame.0.max(start.0))
+                })
+                .sum();
+
+            RegionStats {
+                base: region.base,
+                length: region.length,
+                entry_type: region.entry_type,
+                free_frame_count,
+            }
+        })
+        .collect();
+
+    let zones = Zone::ALL.map(|zone| {
+        let buddy_allocator = FRAME_ALLOCATOR.get_zone(zone);
+
+        ZoneStats {
+            zone,
+            free_frame_count: buddy_allocator.free_frame_count(),
+            total_frame_count: buddy_allocator.total_frame_count(),
+        }
+    });
+
+    MemoryStats {
+        bytes_per_type: get_bytes_per_type(),
+        regions,
+        zones,
+        largest_free_block_bytes: FRAME_ALLOCATOR.find_largest_free_block(),
+        arena_count: PAGE_ALLOCATOR.arena_count(),
+        arena_bytes: PAGE_ALLOCATOR.calculate_total_space(),
+        arena_free_bytes: PAGE_ALLOCATOR.calculate_free_space(),
+        largest_free_run_bytes: PAGE_ALLOCATOR.find_largest_free_run(),
+        slabs: HEAP.slab_stats(),
+        virtual_regions: VMM.lock().regions().to_vec(),
+        early_bytes: EARLY_ALLOCATOR.used(),
+        page_table_bytes: get_page_table_bytes(),
+        kernel_text_bytes: sections::text().len(),
+        kernel_rodata_bytes: sections::rodata().len(),
+        kernel_data_bytes: sections::data().len(),
+        kernel_bytes: sections::kernel().len(),
+    }
+}
+
+fn get_bytes_per_type() -> [(MemoryEntryType, usize); MEMORY_ENTRY_TYPES.len()] {
+    MEMORY_ENTRY_TYPES.map(|entry_type| {
+        let bytes = MEMORY_MAP
+            .iter()
+            .filter(|region| region.entry_type == entry_type)
+            .map(|region| region.length)
+            .sum();
+
+        (entry_type, bytes)
+    })
+}
+
+/// Bytes used by the page tables of the active address space
+fn get_page_table_bytes() -> usize {
+    let mut page_table_count = 1;
+
+    paging::get_active_table().for_each_table(4, &mut |_| page_table_count += 1);
+
+    page_table_count * MIN_PAGE_SIZE
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_memory_map(f, &self.bytes_per_type)?;
+
+        writeln!(f, "regions:")?;
+
+        for region in self.regions.iter() {
+            writeln!(
+                f,
+                "  {:#014x}-{:#014x} {:<24} {:>8} used {:>8} free pages",
+                region.base,
+                region.base + region.length,
+                get_entry_type_name(region.entry_type),
+                region.frame_count() - region.free_frame_count.min(region.frame_count()),
+                region.free_frame_count,
+            )?;
+        }
+
+        writeln!(f, "zones:")?;
+
+        for zone in self.zones.iter() {
+            write_zone(f, zone)?;
+        }
+
+        write_largest_free_block(f, self.largest_free_block_bytes)?;
+
+        write_heap(
+            f,
+            self.arena_count,
+            self.arena_bytes,
+            self.arena_free_bytes,
+            self.largest_free_run_bytes,
+        )?;
+
+        for slab in self.slabs.iter() {
+            write_slab(f, slab)?;
+        }
+
+        writeln!(f, "virtual regions:")?;
+
+        for region in self.virtual_regions.iter() {
+            write_virtual_region(f, region)?;
+        }
+
+        write_early_allocations_and_page_tables(f, self.early_bytes, self.page_table_bytes)?;
+
+        write_kernel_image(
+            f,
+            self.kernel_bytes,
+            self.kernel_text_bytes,
+            self.kernel_rodata_bytes,
+            self.kernel_data_bytes,
+        )
+    }
+}
+
+/// Writes the statistics that can be collected without waiting for a lock and without
+/// allocating, the parts whose lock is held are marked as busy, this is for the out of memory
+/// handler, as the allocation that failed may have been made while holding any allocator lock
+/// or the VMM lock
+pub fn write_stats_without_waiting<W: Write>(w: &mut W) -> fmt::Result {
+    const BUSY: &str = "busy, its lock is held";
+
+    write_memory_map(w, &get_bytes_per_type())?;
+
+    writeln!(w, "zones:")?;
+
+    let mut largest_free_order = None;
+
+    for zone in Zone::ALL {
+        match FRAME_ALLOCATOR.zones[zone as usize].try_lock() {
+            Some(buddy_allocator) => {
+                write_zone(
+                    w,
+                    &ZoneStats {
+                        zone,
+                        free_frame_count: buddy_allocator.free_frame_count(),
+                        total_frame_count: buddy_allocator.total_frame_count(),
+                    },
+                )?;
+
+                largest_free_order = largest_free_order.max(buddy_allocator.largest_free_order());
+            }
+
+            None => writeln!(w, "  {:<24} {BUSY}", zone.as_str())?,
+        }
+    }
+
+    let largest_free_block_bytes = largest_free_order.map_or(0, |order| MIN_PAGE_SIZE << order);
+
+    write_largest_free_block(w, largest_free_block_bytes)?;
+
+    match PAGE_ALLOCATOR.0.try_lock() {
+        Some(page_allocators) => {
+            let arenas = || page_allocators.iter().flatten();
+
+            write_heap(
+                w,
+                arenas().count(),
+                arenas().map(|a| a.page_count * MIN_PAGE_SIZE).sum(),
+                arenas().map(|a| a.calculate_free_space()).sum(),
+                arenas()
+                    .map(|a| a.find_largest_free_run() * MIN_PAGE_SIZE)
+                    .max()
+                    .unwrap_or(0),
+            )?;
+        }
+
+        None => writeln!(w, "heap: {BUSY}")?,
+    }
+
+    for (slab_cache, object_size) in HEAP.slab_caches.iter().zip(SIZE_CLASSES) {
+        match slab_cache.try_lock() {
+            Some(slab_cache) => write_slab(
+                w,
+                &SlabStats {
+                    object_size,
+                    slab_count: slab_cache.slab_count,
+                    used_count: slab_cache.used_count,
+                },
+            )?,
+
+            None => writeln!(w, "  {object_size:>4} byte objects {BUSY}")?,
+        }
+    }
+
+    writeln!(w, "virtual regions:")?;
+
+    match VMM.try_lock() {
+        Some(vmm) => {
+            for region in vmm.regions() {
+                write_virtual_region(w, region)?;
+            }
+        }
+
+        None => writeln!(w, "  {BUSY}")?,
+    }
+
+    write_early_allocations_and_page_tables(w, EARLY_ALLOCATOR.used(), get_page_table_bytes())?;
+
+    write_kernel_image(
+        w,
+        sections::kernel().len(),
+        sections::text().len(),
+        sections::rodata().len(),
+        sections::data().len(),
+    )
+}
+
+fn write_memory_map<W: Write>(
+    w: &mut W,
+    bytes_per_type: &[(MemoryEntryType, usize)],
+) -> fmt::Result {
+    writeln!(w, "memory map:")?;
+
+    for &(entry_type, bytes) in bytes_per_type.iter().filter(|(_, b)| *b != 0) {
+        writeln!(
+            w,
+            "  {:<24} {:>10} KiB",
+            get_entry_type_name(entry_type),
+            bytes / KIB
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_zone<W: Write>(w: &mut W, zone: &ZoneStats) -> fmt::Result {
+    writeln!(
+        w,
+        "  {:<24} {:>8} used {:>8} free pages",
+        zone.zone.as_str(),
+        zone.total_frame_count - zone.free_frame_count,
+        zone.free_frame_count,
+    )
+}
+
+fn write_largest_free_block<W: Write>(w: &mut W, largest_free_block_bytes: usize) -> fmt::Result {
+    writeln!(
+        w,
+        "  largest free block {:>10} KiB",
+        largest_free_block_bytes / KIB
+    )
+}
+
+fn write_heap<W: Write>(
+    w: &mut W,
+    arena_count: usize,
+    arena_bytes: usize,
+    arena_free_bytes: usize,
+    largest_free_run_bytes: usize,
+) -> fmt::Result {
+    writeln!(
+        w,
+        "heap: {} arenas, {} KiB used, {} KiB free, {} KiB largest free run",
+        arena_count,
+        (arena_bytes - arena_free_bytes) / KIB,
+        arena_free_bytes / KIB,
+        largest_free_run_bytes / KIB,
+    )
+}
+
+fn write_slab<W: Write>(w: &mut W, slab: &SlabStats) -> fmt::Result {
+    writeln!(
+        w,
+        "  {:>4} byte objects {:>8} used in {:>6} slabs",
+        slab.object_size, slab.used_count, slab.slab_count,
+    )
+}
+
+fn write_virtual_region<W: Write>(w: &mut W, region: &Region) -> fmt::Result {
+    writeln!(
+        w,
+        "  {:#018x}-{:#018x} {:<24} {:>8} KiB {}{} {}",
+        region.start,
+        region.end(),
+        region.name,
+        region.size() / KIB,
+        if region.flags.is_writable { "w" } else { "-" },
+        if region.flags.is_executable { "x" } else { "-" },
+        region.flags.cache_type.as_str(),
+    )
+}
+
+fn write_early_allocations_and_page_tables<W: Write>(
+    w: &mut W,
+    early_bytes: usize,
+    page_table_bytes: usize,
+) -> fmt::Result {
+    writeln!(w, "early allocations: {} KiB", early_bytes / KIB)?;
+
+    writeln!(w, "page tables: {} KiB", page_table_bytes / KIB)
+}
+
+fn write_kernel_image<W: Write>(
+    w: &mut W,
+    kernel_bytes: usize,
+    text_bytes: usize,
+    rodata_bytes: usize,
+    data_bytes: usize,
+) -> fmt::Result {
+    write!(
+        w,
+        "kernel image: {} KiB ({} KiB text, {} KiB rodata, {} KiB data)",
+        kernel_bytes / KIB,
+        text_bytes / KIB,
+        rodata_bytes / KIB,
+        data_bytes / KIB,
+    )
+}
+
+/// Prints the memory statistics to the kernel log and the serial port
+pub fn print_stats() {
+    let stats = stats();
+
+    println!("{stats}");
+
+    let _ = writeln!(SERIAL.lock(), "{stats}");
+}
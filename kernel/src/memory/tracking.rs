@@ -0,0 +1,237 @@
+use core::{alloc::Layout, fmt::Write};
+
+use spin::Mutex;
+
+use crate::arch::{cpu, serial::SERIAL};
+
+use super::is_owned_by_heap;
+
+/// Passing this flag on the command line prints every live allocation once the kernel booted
+pub const ALLOCATIONS_FLAG: &str = "dump_allocations";
+
+/// Must be a power of two, allocations past this are not tracked
+const MAX_TRACKED_ALLOCATION_COUNT: usize = 1 << 15;
+
+/// Amount of return addresses recorded for each allocation, the first ones are usually in the
+/// allocator itself
+const BACKTRACE_LEN: usize = 6;
+
+#[derive(Clone, Copy)]
+struct TrackedAllocation {
+    /// Zero if this slot is empty
+    address: usize,
+    size: usize,
+    align: usize,
+    cpu_id: Option<usize>,
+    backtrace: [usize; BACKTRACE_LEN],
+}
+
+impl TrackedAllocation {
+    const EMPTY: TrackedAllocation = TrackedAllocation {
+        address: 0,
+        size: 0,
+        align: 0,
+        cpu_id: None,
+        backtrace: [0; BACKTRACE_LEN],
+    };
+
+    #[inline(always)]
+    fn is_empty(&self) -> bool {
+        self.address == 0
+    }
+}
+
+/// A hash table of live allocations, indexed by address with linear probing
+struct AllocationTable {
+    slots: [TrackedAllocation; MAX_TRACKED_ALLOCATION_COUNT],
+    len: usize,
+    /// Whether an allocation was not tracked because the table was full, after which freeing
+    /// an untracked pointer is not necessarily a bug
+    has_overflowed: bool,
+}
+
+impl AllocationTable {
+    #[inline(always)]
+    fn get_ideal_index(address: usize) -> usize {
+        // Fibonacci hashing, the low bits are mostly zero because of alignment
+        (address >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+            >> (usize::BITS - MAX_TRACKED_ALLOCATION_COUNT.trailing_zeros())
+    }
+
+    fn find(&self, address: usize) -> Option<usize> {
+        let mut index = Self::get_ideal_index(address);
+
+        loop {
+            let slot = &self.slots[index];
+
+            if slot.is_empty() {
+                return None;
+            }
+
+            if slot.address == address {
+                return Some(index);
+            }
+
+            index = (index + 1) % MAX_TRACKED_ALLOCATION_COUNT;
+        }
+    }
+
+    fn insert(&mut self, allocation: TrackedAllocation) {
+        // One slot is always left empty so that searching always ends
+        if self.len + 1 >= MAX_TRACKED_ALLOCATION_COUNT {
+            self.has_overflowed = true;
+            return;
+        }
+
+        let mut index = Self::get_ideal_index(allocation.address);
+
+        while !self.slots[index].is_empty() {
+            index = (index + 1) % MAX_TRACKED_ALLOCATION_COUNT;
+        }
+
+        self.slots[index] = allocation;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, mut index: usize) -> TrackedAllocation {
+        let removed = self.slots[index];
+
+        // Shift back the following entries that can't be found anymore with a hole at `index`
+        let mut next_index = index;
+
+        loop {
+            next_index = (next_index + 1) % MAX_TRACKED_ALLOCATION_COUNT;
+
+            let next = self.slots[next_index];
+
+            if next.is_empty() {
+                break;
+            }
+
+            let ideal_index = Self::get_ideal_index(next.address);
+
+            // Whether `ideal_index` is cyclically in (index, next_index], in which case the
+            // entry is still reachable and must stay
+            let is_reachable = if index <= next_index {
+                index < ideal_index && ideal_index <= next_index
+            } else {
+                index < ideal_index || ideal_index <= next_index
+            };
+
+            if !is_reachable {
+                self.slots[index] = next;
+                index = next_index;
+            }
+        }
+
+        self.slots[index] = TrackedAllocation::EMPTY;
+        self.len -= 1;
+
+        removed
+    }
+}
+
+static ALLOCATIONS: Mutex<AllocationTable> = Mutex::new(AllocationTable {
+    slots: [TrackedAllocation::EMPTY; MAX_TRACKED_ALLOCATION_COUNT],
+    len: 0,
+    has_overflowed: false,
+});
+
+pub fn track_alloc(ptr: *mut u8, layout: Layout) {
+    if ptr.is_null() {
+        return;
+    }
+
+    ALLOCATIONS.lock().insert(TrackedAllocation {
+        address: ptr.addr(),
+        size: layout.size(),
+        align: layout.align(),
+        cpu_id: cpu::get_current_id(),
+        backtrace: cpu::get_return_addresses(),
+    });
+}
+
+/// Panics on double frees, frees of pointers that no heap region owns, and frees with another
+/// layout than the one used to allocate
+pub fn track_dealloc(ptr: *mut u8, layout: Layout) {
+    let mut allocations = ALLOCATIONS.lock();
+
+    let Some(index) = allocations.find(ptr.addr()) else {
+        let has_overflowed = allocations.has_overflowed;
+
+        // Panicking allocates, so the lock must be dropped first
+        drop(allocations);
+
+        if has_overflowed {
+            return;
+        }
+
+        if is_owned_by_heap(ptr.addr()) {
+            panic!("double free of {ptr:p}");
+        } else {
+            panic!("freeing {ptr:p}, which is not owned by any heap region");
+        }
+    };
+
+    let allocation = allocations.remove(index);
+
+    drop(allocations);
+
+    assert!(
+        allocation.size == layout.size() && allocation.align == layout.align(),
+        "freeing {ptr:p} with size {} and alignment {}, but it was allocated with size {} and alignment {}",
+        layout.size(),
+        layout.align(),
+        allocation.size,
+        allocation.align,
+    );
+}
+
+pub fn get_tracked_allocation_count() -> usize {
+    ALLOCATIONS.lock().len
+}
+
+/// Prints every live allocation to the serial port, along with where it was allocated, and
+/// a summary to the kernel log
+pub fn dump_allocations() {
+    let allocations = ALLOCATIONS.lock();
+
+    let mut serial = SERIAL.lock();
+
+    let mut total_size = 0;
+
+    for allocation in allocations.slots.iter().filter(|a| !a.is_empty()) {
+        let _ = write!(
+            serial,
+            "allocation {:#x} of {} bytes aligned to {} on cpu ",
+            allocation.address, allocation.size, allocation.align,
+        );
+
+        let _ = match allocation.cpu_id {
+            Some(cpu_id) => write!(serial, "{cpu_id}"),
+            None => write!(serial, "?"),
+        };
+
+        let _ = write!(serial, ", allocated from");
+
+        for &return_address in allocation.backtrace.iter().filter(|&&a| a != 0) {
+            let _ = write!(serial, " {return_address:#x}");
+        }
+
+        let _ = writeln!(serial);
+
+        total_size += allocation.size;
+    }
+
+    let len = allocations.len;
+    let has_overflowed = allocations.has_overflowed;
+
+    drop(serial);
+    drop(allocations);
+
+    println!("memory: {len} live allocations, {total_size} bytes in total");
+
+    if has_overflowed {
+        println!("memory: some allocations were not tracked because there were too many of them");
+    }
+}
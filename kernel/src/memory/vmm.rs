@@ -0,0 +1,388 @@
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+
+use spin::{Mutex, MutexGuard};
+
+use crate::{
+    allocators::buddy_allocator::Frame,
+    arch::tlb::{ALL_CPUS, TlbShootdown},
+    paging::{self, CacheType, MIN_PAGE_SIZE, PageTable},
+};
+
+use super::{alloc_frame, free_frame};
+
+/// Start of the kernel's virtual addresses that are managed by the VMM, right after the higher
+/// half direct map, which can not cover more than 64 TiB of physical memory
+pub const VMM_START: usize = 0xffff_c000_0000_0000;
+
+/// End of the kernel's virtual addresses that are managed by the VMM
+pub const VMM_END: usize = 0xffff_e000_0000_0000;
+
+/// Every region is followed by an unmapped page, so overflowing a region faults instead of
+/// corrupting the next one
+pub(super) const GUARD_SIZE: usize = MIN_PAGE_SIZE;
+
+/// Amount of pages that are unmapped before their translations are flushed on every CPU, which
+/// must happen before their frames are freed
+const UNMAP_BATCH_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionFlags {
+    pub is_writable: bool,
+    pub is_executable: bool,
+    pub cache_type: CacheType,
+}
+
+impl RegionFlags {
+    pub const DATA: RegionFlags = RegionFlags {
+        is_writable: true,
+        is_executable: false,
+        cache_type: CacheType::WriteBack,
+    };
+
+    pub const READ_ONLY: RegionFlags = RegionFlags {
+        is_writable: false,
+        is_executable: false,
+        cache_type: CacheType::WriteBack,
+    };
+}
+
+/// A range of virtual addresses that the VMM handed out
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub start: usize,
+    pub page_count: usize,
+    /// Who the region belongs to, for debugging
+    pub name: &'static str,
+    pub flags: RegionFlags,
+    /// Whether the frames behind the region were allocated by the VMM, and are freed with it
+    pub owns_frames: bool,
+    /// Whether the region is a stack, whose first page is left unmapped, so a stack that grows
+    /// past its end faults instead of corrupting the region before it
+    pub is_stack: bool,
+}
+
+impl Region {
+    #[inline(always)]
+    pub fn end(&self) -> usize {
+        self.start + self.page_count * MIN_PAGE_SIZE
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> usize {
+        self.page_count * MIN_PAGE_SIZE
+    }
+
+    /// Whether `address` is in the guard page after the region, or in the one at the start of a
+    /// stack
+    #[inline(always)]
+    pub fn is_in_guard(&self, address: usize) -> bool {
+        (self.end()..self.end() + GUARD_SIZE).contains(&address) || self.is_in_stack_guard(address)
+    }
+
+    /// Whether `address` is in the unmapped page at the start of a stack, which is what a stack
+    /// overflow hits
+    #[inline(always)]
+    pub fn is_in_stack_guard(&self, address: usize) -> bool {
+        self.is_stack && (self.start..self.start + GUARD_SIZE).contains(&address)
+    }
+}
+
+/// The regions of the kernel's virtual addresses that are in use, sorted by their start
+pub struct Vmm {
+    regions: Vec<Region>,
+}
+
+impl Vmm {
+    pub const fn new() -> Vmm {
+        Vmm {
+            regions: Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// The region that `address` is in, the guard page after a region is not part of it, but the
+    /// one at the start of a stack is
+    pub fn find(&self, address: usize) -> Option<&Region> {
+        find_preceding(&self.regions, address).filter(|region| address < region.end())
+    }
+
+    /// The region that has `address` in its guard page
+    pub fn find_guarded(&self, address: usize) -> Option<&Region> {
+        find_preceding(&self.regions, address).filter(|region| region.is_in_guard(address))
+    }
+
+    /// Finds the first gap that fits `page_count` pages and a guard page, and records a region
+    /// there, returns `None` if there is no such gap
+    fn reserve(
+        &mut self,
+        page_count: usize,
+        name: &'static str,
+        flags: RegionFlags,
+        owns_frames: bool,
+        is_stack: bool,
+    ) -> Option<Region> {
+        let size = page_count
+            .checked_mul(MIN_PAGE_SIZE)?
+            .checked_add(GUARD_SIZE)?;
+
+        let mut start = VMM_START;
+        let mut index = 0;
+
+        for region in self.regions.iter() {
+            if region.start - start >= size {
+                break;
+            }
+
+            start = region.end() + GUARD_SIZE;
+            index += 1;
+        }
+
+        if VMM_END - start < size {
+            return None;
+        }
+
+        let region = Region {
+            start,
+            page_count,
+            name,
+            flags,
+            owns_frames,
+            is_stack,
+        };
+
+        self.regions.insert(index, region);
+
+        Some(region)
+    }
+
+    /// Forgets the region that starts at `start`
+    fn release(&mut self, start: usize) -> Option<Region> {
+        let index = self
+            .regions
+            .binary_search_by_key(&start, |region| region.start)
+            .ok()?;
+
+        Some(self.regions.remove(index))
+    }
+}
+
+impl Default for Vmm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The lock is held while pages are mapped, so CPUs never create the same table twice, it is not
+/// held while unmapping, which only changes tables that exist, and waits for other CPUs to flush
+pub static VMM: Mutex<Vmm> = Mutex::new(Vmm::new());
+
+/// Allocates `size` bytes of virtually contiguous memory, which is backed by frames that are not
+/// necessarily contiguous, so it works even when physical memory is fragmented
+pub fn vmalloc(size: usize, name: &'static str, flags: RegionFlags) -> Option<NonNull<u8>> {
+    let region = alloc_region(size.div_ceil(MIN_PAGE_SIZE), name, flags, false)?;
+
+    NonNull::new(region.start as *mut u8)
+}
+
+/// Allocates a stack of `size` bytes with an unmapped page below it, returns the address right
+/// after its end, which is where the stack pointer starts, stacks are never freed
+pub fn alloc_stack(size: usize, name: &'static str) -> Option<usize> {
+    let page_count = size.div_ceil(MIN_PAGE_SIZE) + 1;
+
+    let region = alloc_region(page_count, name, RegionFlags::DATA, true)?;
+
+    Some(region.end())
+}
+
+/// Reserves a region of `page_count` pages and backs them with frames, except for the first page
+/// of a stack
+fn alloc_region(
+    page_count: usize,
+    name: &'static str,
+    flags: RegionFlags,
+    is_stack: bool,
+) -> Option<Region> {
+    // Dropped after the lock, so other CPUs are not waited for while holding it
+    let mut shootdown = TlbShootdown::new(ALL_CPUS);
+
+    let mut vmm = VMM.lock();
+
+    let region = vmm.reserve(page_count, name, flags, true, is_stack)?;
+
+    let page_table = paging::get_active_table();
+
+    let mapped_start = if is_stack {
+        region.start + GUARD_SIZE
+    } else {
+        region.start
+    };
+
+    for virt in (mapped_start..region.end()).step_by(MIN_PAGE_SIZE) {
+        let Some(frame) = alloc_frame() else {
+            release_partially_mapped(vmm, page_table, region, virt);
+            return None;
+        };
+
+        match page_table.try_map(virt, frame.phys(), &mut shootdown) {
+            Ok(entry) => set_flags(entry, flags),
+
+            Err(_) => {
+                free_frame(frame);
+                release_partially_mapped(vmm, page_table, region, virt);
+                return None;
+            }
+        }
+    }
+
+    Some(region)
+}
+
+/// Maps `frames` one after the other in a new region, they are not freed with the region
+pub fn vmap(
+    frames: impl ExactSizeIterator<Item = Frame>,
+    name: &'static str,
+    flags: RegionFlags,
+) -> Option<NonNull<u8>> {
+    // Dropped after the lock, so other CPUs are not waited for while holding it
+    let mut shootdown = TlbShootdown::new(ALL_CPUS);
+
+    let mut vmm = VMM.lock();
+
+    let region = vmm.reserve(frames.len(), name, flags, false, false)?;
+
+    let page_table = paging::get_active_table();
+
+    for (virt, frame) in (region.start..region.end())
+        .step_by(MIN_PAGE_SIZE)
+        .zip(frames)
+    {
+        match page_table.try_map(virt, frame.phys(), &mut shootdown) {
+            Ok(entry) => set_flags(entry, flags),
+
+            Err(_) => {
+                release_partially_mapped(vmm, page_table, region, virt);
+                return None;
+            }
+        }
+    }
+
+    NonNull::new(region.start as *mut u8)
+}
+
+/// Unmaps a region that was returned by `vmalloc` or `vmap`, the frames behind it are freed if
+/// it was allocated by `vmalloc`
+pub fn vfree(ptr: NonNull<u8>) {
+    let region = VMM
+        .lock()
+        .find(ptr.addr().get())
+        .filter(|region| region.start == ptr.addr().get())
+        .copied()
+        .expect("freed an address that is not the start of a virtual memory region");
+
+    // The region is only released once its pages are flushed, so its addresses are not handed
+    // out again before that
+    unmap_pages(
+        paging::get_active_table(),
+        region.start..region.end(),
+        region.owns_frames,
+        ALL_CPUS,
+    );
+
+    VMM.lock().release(region.start);
+}
+
+/// The last region of `regions` that starts at or before `address`, `regions` must be sorted by
+/// their start
+pub(super) fn find_preceding(regions: &[Region], address: usize) -> Option<&Region> {
+    let index = regions
+        .partition_point(|region| region.start <= address)
+        .checked_sub(1)?;
+
+    Some(&regions[index])
+}
+
+/// Records `region` in `regions`, which are sorted by their start, returns `false` if it or its
+/// guard page overlaps another region or its guard page
+pub(super) fn insert_region(regions: &mut Vec<Region>, region: Region) -> bool {
+    let index = regions.partition_point(|other| other.start < region.start);
+
+    let fits_after_previous = index
+        .checked_sub(1)
+        .is_none_or(|previous| regions[previous].end() + GUARD_SIZE <= region.start);
+
+    let fits_before_next = regions
+        .get(index)
+        .is_none_or(|next| region.end() + GUARD_SIZE <= next.start);
+
+    if !fits_after_previous || !fits_before_next {
+        return false;
+    }
+
+    regions.insert(index, region);
+
+    true
+}
+
+/// Undoes a region that ran out of memory while its pages were mapped, up to `mapped_end`
+fn release_partially_mapped(
+    vmm: MutexGuard<'_, Vmm>,
+    page_table: &mut PageTable,
+    region: Region,
+    mapped_end: usize,
+) {
+    // Unmapping waits for other CPUs to flush, which must not be done while holding the lock
+    drop(vmm);
+
+    unmap_pages(
+        page_table,
+        region.start..mapped_end,
+        region.owns_frames,
+        ALL_CPUS,
+    );
+
+    VMM.lock().release(region.start);
+}
+
+#[inline(always)]
+fn set_flags(entry: &mut paging::Entry, flags: RegionFlags) {
+    entry
+        .set_writable(flags.is_writable)
+        .set_executability(flags.is_executable)
+        .set_cache_type(flags.cache_type);
+}
+
+/// Unmaps the pages in `range`, the translations of them are flushed on the CPUs in `cpus`
+/// before the frames behind them are freed, if `free_frames` is set
+pub(super) fn unmap_pages(
+    page_table: &mut PageTable,
+    range: core::ops::Range<usize>,
+    free_frames: bool,
+    cpus: u64,
+) {
+    for batch_start in range.clone().step_by(UNMAP_BATCH_SIZE * MIN_PAGE_SIZE) {
+        let batch_end = (batch_start + UNMAP_BATCH_SIZE * MIN_PAGE_SIZE).min(range.end);
+
+        let mut frames = [None; UNMAP_BATCH_SIZE];
+        let mut shootdown = TlbShootdown::new(cpus);
+
+        for (virt, frame) in (batch_start..batch_end)
+            .step_by(MIN_PAGE_SIZE)
+            .zip(frames.iter_mut())
+        {
+            *frame = page_table.translate(virt).map(Frame::from_phys);
+
+            page_table.unmap(virt, &mut shootdown);
+        }
+
+        shootdown.flush();
+
+        if free_frames {
+            frames.into_iter().flatten().for_each(free_frame);
+        }
+    }
+}
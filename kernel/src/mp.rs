@@ -1,12 +1,41 @@
-use crate::requests::MP_REQUEST;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{arch::cpu, memory, paging, requests::MP_REQUEST};
 
 pub const MAX_CPU_COUNT: usize = 64;
 
+/// Size of the stack that each CPU runs the kernel on, there is an unmapped page below it, so
+/// overflowing it is caught
+pub const KERNEL_STACK_SIZE: usize = 64 * 1024;
+
+/// Amount of application processors that entered the kernel, once they did, they don't need
+/// limine's information about them, nor the stacks that limine gave them anymore
+static STARTED_AP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Moves the CPU we are running on off the stack that limine gave it, onto a kernel stack, and
+/// calls `f` with `argument` there, must be called after paging is initialized
+pub fn switch_to_kernel_stack(f: extern "C" fn(usize) -> !, argument: usize) -> ! {
+    let stack_end = memory::alloc_stack(KERNEL_STACK_SIZE, "kernel stack")
+        .expect("out of memory for a kernel stack");
+
+    cpu::switch_stack(stack_end, f, argument)
+}
+
 extern "C" fn init_ap(limine_cpu: &limine::mp::Cpu) -> ! {
-    crate::init_ap(limine_cpu.id);
+    // The bootloader's page tables are reclaimed once every application processor started
+    paging::init_ap();
+
+    switch_to_kernel_stack(start_ap, limine_cpu.id as usize)
 }
 
-/// Boot application processors by directing them into kernel code that they can execute
+extern "C" fn start_ap(cpu_id: usize) -> ! {
+    STARTED_AP_COUNT.fetch_add(1, Ordering::Release);
+
+    crate::init_ap(cpu_id as u32);
+}
+
+/// Boot application processors by directing them into kernel code that they can execute, and
+/// wait for all of them to enter it
 pub fn boot_ap() {
     let mut mp_request = MP_REQUEST.lock();
 
@@ -14,7 +43,19 @@ pub fn boot_ap() {
         .get_response_mut()
         .expect("could ask limine for multiproccessing information");
 
+    let bsp_lapic_id = mp_respone.bsp_lapic_id();
+
+    let mut ap_count = 0;
+
     for limine_cpu in mp_respone.cpus_mut().iter_mut().take(MAX_CPU_COUNT) {
-        limine_cpu.goto_address.write(init_ap);
+        if limine_cpu.lapic_id != bsp_lapic_id {
+            limine_cpu.goto_address.write(init_ap);
+
+            ap_count += 1;
+        }
+    }
+
+    while STARTED_AP_COUNT.load(Ordering::Acquire) < ap_count {
+        core::hint::spin_loop();
     }
 }
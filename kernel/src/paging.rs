@@ -1,7 +1,18 @@
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use lazy_static::lazy_static;
+use limine::memory_map::EntryType as MemoryEntryType;
 
 pub use crate::arch::paging::*;
-use crate::requests::HHDM_REQUEST;
+use crate::{
+    arch::{cpu, sections, tlb::TlbShootdown},
+    memory::{self, MEMORY_MAP},
+    requests::{EXECUTABLE_ADDRESS_REQUEST, HHDM_REQUEST},
+    screen,
+};
 
 lazy_static! {
     static ref HHDM_OFFSET: usize = HHDM_REQUEST
@@ -13,3 +24,276 @@ lazy_static! {
 pub fn offset(phys: usize) -> usize {
     phys + *HHDM_OFFSET
 }
+
+/// Physical address of the level 4 page table that the kernel built for itself, every CPU
+/// switches to it
+static KERNEL_TABLE_PHYS: AtomicUsize = AtomicUsize::new(0);
+
+/// The page table that the kernel built for itself in `init`
+pub fn get_kernel_table() -> &'static mut PageTable {
+    let phys = KERNEL_TABLE_PHYS.load(Ordering::Acquire);
+
+    assert_ne!(phys, 0, "the kernel page table is not built yet");
+
+    unsafe { &mut *(offset(phys) as *mut PageTable) }
+}
+
+/// Builds the kernel's own page table and switches to it, which maps the kernel image with the
+/// permissions of each section, the higher half direct map and the framebuffer, everything
+/// else that limine mapped for us is gone after this
+pub fn init() {
+    enable_no_execute();
+
+    // The framebuffer is mapped write combining, which needs our PAT before it is used
+    init_pat();
+
+    let p4_frame = memory::alloc_frame().expect("out of memory for page tables");
+    let p4_table = unsafe { &mut *(p4_frame.virt() as *mut PageTable) };
+
+    *p4_table = PageTable::empty();
+
+    // No CPU uses the table yet, so there is nothing to shoot down on other CPUs
+    let mut shootdown = TlbShootdown::new(0);
+
+    map_kernel_image(p4_table, &mut shootdown);
+
+    // Reserved memory and bad memory are never accessed through the direct map
+    for region in MEMORY_MAP.iter().filter(|region| {
+        region.entry_type != MemoryEntryType::RESERVED
+            && region.entry_type != MemoryEntryType::BAD_MEMORY
+    }) {
+        assert!(
+            offset(region.base + region.length) <= memory::VMM_START,
+            "the higher half direct map overlaps the addresses of the VMM"
+        );
+
+        // Mapping the framebuffer with a different memory type anywhere else would conflict
+        let cache_type = if region.entry_type == MemoryEntryType::FRAMEBUFFER {
+            CacheType::WriteCombining
+        } else {
+            CacheType::WriteBack
+        };
+
+        map_direct(
+            p4_table,
+            region.base..region.base + region.length,
+            cache_type,
+            &mut shootdown,
+        );
+    }
+
+    // The framebuffer is usually in the memory map, but that is not guaranteed, it is write
+    // combining so drawing does not wait for every pixel to reach the screen
+    if let Some(framebuffer) = screen::get_available() {
+        let phys = framebuffer.addr().addr() - *HHDM_OFFSET;
+
+        map_direct(
+            p4_table,
+            phys..phys + framebuffer.size(),
+            CacheType::WriteCombining,
+            &mut shootdown,
+        );
+    }
+
+    // Address spaces share the kernel's level 3 tables, so the kernel can map things later
+    // without touching every address space
+    p4_table.populate_higher_half();
+
+    KERNEL_TABLE_PHYS.store(p4_frame.phys(), Ordering::Release);
+
+    set_active_table(p4_table);
+}
+
+/// Switches an application processor to the kernel's page table, which must be done before the
+/// bootloader's page tables are reclaimed
+pub fn init_ap() {
+    enable_no_execute();
+
+    // Each CPU has a PAT of its own, it must match the memory types of the kernel's page table
+    init_pat();
+
+    set_active_table(get_kernel_table());
+}
+
+/// Maps `.text` as read only and executable, `.rodata` as read only, and `.data` along with
+/// `.bss` as writable
+fn map_kernel_image(p4_table: &mut PageTable, shootdown: &mut TlbShootdown) {
+    let response = EXECUTABLE_ADDRESS_REQUEST
+        .get_response()
+        .expect("could not ask limine to get the address of the kernel");
+
+    let virtual_base = response.virtual_base() as usize;
+    let physical_base = response.physical_base() as usize;
+
+    for (section, is_writable, is_executable) in [
+        (sections::text(), false, true),
+        (sections::rodata(), false, false),
+        (sections::data(), true, false),
+    ] {
+        // Sections start on a page of their own, so pages are never shared between them
+        for virt in (section.start..section.end).step_by(MIN_PAGE_SIZE) {
+            p4_table
+                .map(virt, virt - virtual_base + physical_base, shootdown)
+                .set_writable(is_writable)
+                .set_executability(is_executable);
+        }
+    }
+}
+
+/// Maps physical memory at its place in the higher half direct map as writable and not
+/// executable with the memory type `cache_type`, using the biggest pages that fit
+fn map_direct(
+    p4_table: &mut PageTable,
+    phys_range: Range<usize>,
+    cache_type: CacheType,
+    shootdown: &mut TlbShootdown,
+) {
+    let mut phys = memory::align_down(phys_range.start, MIN_PAGE_SIZE);
+    let end = memory::align_up(phys_range.end, MIN_PAGE_SIZE);
+
+    while phys < end {
+        let virt = offset(phys);
+
+        let fits = |size: HugePageSize| {
+            phys.is_multiple_of(size.bytes())
+                && virt.is_multiple_of(size.bytes())
+                && phys + size.bytes() <= end
+        };
+
+        let huge_page_size = if fits(HugePageSize::Size1GiB) && cpu::supports_1gib_pages() {
+            Some(HugePageSize::Size1GiB)
+        } else if fits(HugePageSize::Size2MiB) {
+            Some(HugePageSize::Size2MiB)
+        } else {
+            None
+        };
+
+        let page_size = match huge_page_size {
+            Some(size) => {
+                p4_table
+                    .map_huge(virt, phys, size, shootdown)
+                    .set_writable(true)
+                    .set_executability(false)
+                    .set_huge_cache_type(cache_type);
+
+                size.bytes()
+            }
+
+            None => {
+                p4_table
+                    .map(virt, phys, shootdown)
+                    .set_writable(true)
+                    .set_executability(false)
+                    .set_cache_type(cache_type);
+
+                MIN_PAGE_SIZE
+            }
+        };
+
+        phys += page_size;
+    }
+}
+
+/// Checks that `translate` finds what `map` and `map_huge` mapped at every level, also after
+/// huge pages were split, on a level 4 table of its own, panics on the first check that fails
+#[cfg(feature = "self_checks")]
+pub fn run_self_checks() {
+    const GIB: usize = 1024 * 1024 * 1024;
+
+    // The mapped frames are never accessed, so any physical address can be used
+    const BASE: usize = 0x0000_4000_0000_0000;
+
+    let p4_frame = memory::alloc_frame().expect("out of memory for page tables");
+    let p4_table = unsafe { &mut *(p4_frame.virt() as *mut PageTable) };
+
+    *p4_table = PageTable::empty();
+
+    // The table is never active, so there is nothing to shoot down on other CPUs
+    let mut shootdown = TlbShootdown::new(0);
+
+    let check = |p4_table: &PageTable, virt: usize, expected: Option<usize>| {
+        let phys = p4_table.translate(virt);
+
+        assert_eq!(
+            phys, expected,
+            "translating {virt:#x} gave {phys:x?} instead of {expected:x?}"
+        );
+    };
+
+    // A 4 KiB page
+    let virt = BASE + 5 * MIN_PAGE_SIZE;
+    let phys = 0x1234_5000;
+
+    p4_table.map(virt, phys, &mut shootdown);
+
+    check(p4_table, virt, Some(phys));
+    check(p4_table, virt + 0xabc, Some(phys + 0xabc));
+    check(p4_table, virt - 1, None);
+    check(p4_table, virt + MIN_PAGE_SIZE, None);
+
+    // A 2 MiB page, which is then split by unmapping a 4 KiB page in it
+    let size = HugePageSize::Size2MiB.bytes();
+    let virt = BASE + 3 * size;
+    let phys = 7 * size;
+
+    p4_table.map_huge(virt, phys, HugePageSize::Size2MiB, &mut shootdown);
+
+    check(p4_table, virt, Some(phys));
+    check(p4_table, virt + 0x1_2345, Some(phys + 0x1_2345));
+    check(p4_table, virt + size - 1, Some(phys + size - 1));
+    check(p4_table, virt + size, None);
+
+    let split_offset = 3 * MIN_PAGE_SIZE;
+
+    p4_table.unmap(virt + split_offset, &mut shootdown);
+
+    check(p4_table, virt + split_offset, None);
+
+    // The pages next to it are still mapped
+    let next_offset = split_offset + MIN_PAGE_SIZE;
+
+    check(p4_table, virt + next_offset, Some(phys + next_offset));
+    check(p4_table, virt + size - 1, Some(phys + size - 1));
+
+    p4_table.unmap_huge(virt, HugePageSize::Size2MiB, &mut shootdown);
+
+    check(p4_table, virt + next_offset, None);
+
+    // A 1 GiB page, which is then split down to 4 KiB pages
+    if cpu::supports_1gib_pages() {
+        let virt = BASE + 2 * GIB;
+        let phys = 3 * GIB;
+
+        p4_table.map_huge(virt, phys, HugePageSize::Size1GiB, &mut shootdown);
+
+        check(p4_table, virt, Some(phys));
+        check(p4_table, virt + 0x1234_5678, Some(phys + 0x1234_5678));
+        check(p4_table, virt + GIB - 1, Some(phys + GIB - 1));
+        check(p4_table, virt + GIB, None);
+
+        let split_offset = 5 * HugePageSize::Size2MiB.bytes();
+
+        p4_table.unmap(virt + split_offset, &mut shootdown);
+
+        check(p4_table, virt + split_offset, None);
+        check(p4_table, virt + split_offset + 1, None);
+
+        let next_offset = split_offset + MIN_PAGE_SIZE;
+
+        check(p4_table, virt + next_offset, Some(phys + next_offset));
+        check(p4_table, virt + 0x1234_5678, Some(phys + 0x1234_5678));
+
+        p4_table.unmap_huge(virt, HugePageSize::Size1GiB, &mut shootdown);
+
+        check(p4_table, virt + 0x1234_5678, None);
+    }
+
+    // Frees the tables that were unlinked by mapping or unmapping huge pages
+    shootdown.flush();
+
+    p4_table.free_lower_half();
+
+    memory::free_frame(p4_frame);
+
+    println!("paging: self checks passed");
+}
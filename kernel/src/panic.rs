@@ -1,17 +1,25 @@
 use core::fmt::Write;
 
-use crate::{arch, console::CONSOLE, requests::FRAMEBUFFER_REQUEST, screen::Color};
+use crate::{
+    arch,
+    console::{self, KERNEL_LOG_TERMINAL},
+    memory,
+    screen::{self, Color},
+    screenshot,
+};
 
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    // Printing the panic info allocates, which must not fail even if we are out of memory
+    memory::enable_reserve_pool();
+
     // We print panic info only if screen can be initialized, otherwise that would make a
     // stack overflow, because if screen can not be initialized, it will panic, therefore
     // calling the panic handler again
-    if FRAMEBUFFER_REQUEST
-        .get_response()
-        .is_some_and(|response| response.framebuffers().next().is_some())
-    {
-        let mut console = CONSOLE.lock();
+    if screen::is_available() {
+        console::switch_terminal(KERNEL_LOG_TERMINAL);
+
+        let mut console = console::get_terminal(KERNEL_LOG_TERMINAL);
 
         console.background = Color::new(0, 128, 255);
         console.foreground = Color::WHITE;
@@ -36,10 +44,17 @@ fn panic_handler(info: &core::panic::PanicInfo) -> ! {
         );
 
         let _ = writeln!(&mut console, "Panic message: {}", info.message());
+
+        drop(console);
+
+        screenshot::dump_on_panic_if_requested();
     }
 
     arch::interrupts::disable();
 
+    // Other CPUs must not wait for us to flush their shootdowns once we halt
+    arch::tlb::leave();
+
     loop {
         arch::interrupts::wait_for_interrupts();
     }
@@ -49,4 +49,29 @@ impl Psf2Font<'_> {
             data: &data[32..data.len()],
         }
     }
+
+    /// Amount of bytes that make up a single row of a glyph
+    #[inline(always)]
+    pub fn get_bytes_per_row(&self) -> usize {
+        (self.header.glyph_width as usize).div_ceil(8)
+    }
+
+    /// Get the bitmap of the glyph for `ch`, characters outside of ASCII use the first glyph
+    pub fn get_glyph(&self, ch: char) -> &[u8] {
+        let glyph_size = self.header.glyph_size as usize;
+
+        let index = if ch.is_ascii() {
+            (ch as usize * glyph_size).rem_euclid(self.data.len())
+        } else {
+            0
+        };
+
+        &self.data[index..index + glyph_size]
+    }
+
+    /// Whether the pixel at (`x`, `y`) of the glyph is set, where (0, 0) is the top left pixel
+    #[inline(always)]
+    pub fn get_glyph_bit(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        (glyph[y * self.get_bytes_per_row() + x / 8] & (0x80 >> (x % 8))) != 0
+    }
 }
@@ -1,15 +1,75 @@
 use lazy_static::lazy_static;
-use limine::framebuffer::Framebuffer;
+use spin::Lazy;
 
 use crate::requests::FRAMEBUFFER_REQUEST;
 
-lazy_static! {
-    pub static ref FRAMEBUFFER: Framebuffer<'static> = FRAMEBUFFER_REQUEST
+/// Our own copy of limine's framebuffer information, which lives in memory that is reclaimed
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    addr: *mut u8,
+    width: u64,
+    height: u64,
+    pitch: u64,
+}
+
+unsafe impl Send for Framebuffer {}
+unsafe impl Sync for Framebuffer {}
+
+impl Framebuffer {
+    #[inline(always)]
+    pub fn addr(&self) -> *mut u8 {
+        self.addr
+    }
+
+    #[inline(always)]
+    pub fn width(&self) -> u64 {
+        self.width
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Amount of bytes between the start of two rows
+    #[inline(always)]
+    pub fn pitch(&self) -> u64 {
+        self.pitch
+    }
+
+    /// Amount of bytes of the whole framebuffer
+    #[inline(always)]
+    pub fn size(&self) -> usize {
+        (self.pitch * self.height) as usize
+    }
+}
+
+/// `None` if limine did not give us any framebuffer
+static AVAILABLE_FRAMEBUFFER: Lazy<Option<Framebuffer>> = Lazy::new(|| {
+    FRAMEBUFFER_REQUEST
         .get_response()
-        .expect("could not ask limine to get the framebuffers")
-        .framebuffers()
-        .next()
-        .expect("no framebuffers are available");
+        .and_then(|response| response.framebuffers().next())
+        .map(|framebuffer| Framebuffer {
+            addr: framebuffer.addr(),
+            width: framebuffer.width(),
+            height: framebuffer.height(),
+            pitch: framebuffer.pitch(),
+        })
+});
+
+lazy_static! {
+    pub static ref FRAMEBUFFER: Framebuffer =
+        AVAILABLE_FRAMEBUFFER.expect("no framebuffers are available");
+}
+
+/// Whether there is a framebuffer, which can be checked without panicking
+pub fn is_available() -> bool {
+    AVAILABLE_FRAMEBUFFER.is_some()
+}
+
+/// The framebuffer, or `None` if there is none, which can be checked without panicking
+pub fn get_available() -> Option<&'static Framebuffer> {
+    AVAILABLE_FRAMEBUFFER.as_ref()
 }
 
 #[derive(Clone, Copy)]
@@ -0,0 +1,114 @@
+use core::fmt::Write;
+
+use crate::{
+    arch::serial::{COM1_PORT, SERIAL, SerialPort},
+    cmdline,
+    graphics::{self, Canvas, Rgba},
+    image::{ppm, qoi},
+};
+
+/// Every screenshot sent over serial is framed by a line that starts with this magic, which is
+/// followed by the format, the width, the height and the amount of bytes of the encoded image:
+///
+/// ```text
+/// FAJR-SCREENSHOT qoi 1280 800 52731
+/// <52731 bytes of image data>
+/// FAJR-SCREENSHOT-END
+/// ```
+pub const FRAME_MAGIC: &str = "FAJR-SCREENSHOT";
+
+/// Passing this flag on the command line sends a screenshot when a panic happens
+pub const ON_PANIC_FLAG: &str = "screenshot_on_panic";
+
+/// Passing this flag on the command line sends a screenshot once the kernel finished booting
+pub const ON_BOOT_FLAG: &str = "screenshot_on_boot";
+
+/// Passing `screenshot_format=ppm` on the command line changes the format of screenshots
+pub const FORMAT_KEY: &str = "screenshot_format";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ppm,
+    Qoi,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Ppm => "ppm",
+            Format::Qoi => "qoi",
+        }
+    }
+
+    /// The format chosen on the command line, QOI is the default since it is much smaller
+    pub fn from_cmdline() -> Format {
+        match cmdline::get_value(FORMAT_KEY) {
+            Some("ppm") => Format::Ppm,
+            _ => Format::Qoi,
+        }
+    }
+}
+
+fn encode<O: FnMut(&[u8])>(canvas: &Canvas, format: Format, output: O) {
+    let get_pixel = |x: usize, y: usize| {
+        canvas
+            .get_pixel(x as isize, y as isize)
+            .map_or(Rgba::new(0, 0, 0, 255), Rgba::from)
+    };
+
+    match format {
+        Format::Ppm => ppm::encode(canvas.width(), canvas.height(), get_pixel, output),
+        Format::Qoi => qoi::encode(canvas.width(), canvas.height(), get_pixel, output),
+    }
+}
+
+/// Streams the current contents of the framebuffer over serial, meant to be bound to a key
+/// combo as well
+pub fn dump(format: Format) {
+    dump_to(&mut SERIAL.lock(), format);
+}
+
+fn dump_to(serial: &mut SerialPort, format: Format) {
+    let canvas = graphics::get_screen_canvas();
+
+    // The image is encoded twice, once to know its size for the header, and once to actually
+    // send it, this way we never allocate, which matters when we are called from a panic
+    let mut length = 0;
+
+    encode(&canvas, format, |bytes| length += bytes.len());
+
+    let _ = writeln!(
+        serial,
+        "\n{FRAME_MAGIC} {} {} {} {length}",
+        format.as_str(),
+        canvas.width(),
+        canvas.height(),
+    );
+
+    encode(&canvas, format, |bytes| serial.write_bytes(bytes));
+
+    let _ = writeln!(serial, "\n{FRAME_MAGIC}-END");
+}
+
+/// Sends a screenshot if `flag` was passed on the command line
+pub fn dump_if_requested(flag: &str) {
+    if cmdline::has_flag(flag) {
+        dump(Format::from_cmdline());
+    }
+}
+
+/// Sends a screenshot if `ON_PANIC_FLAG` was passed on the command line, the panic may have
+/// happened while the serial port was locked, then it is written to without the lock, rather
+/// than waiting forever
+pub fn dump_on_panic_if_requested() {
+    if !cmdline::has_flag(ON_PANIC_FLAG) {
+        return;
+    }
+
+    let format = Format::from_cmdline();
+
+    match SERIAL.try_lock() {
+        Some(mut serial) => dump_to(&mut serial, format),
+        None => dump_to(&mut SerialPort::new(COM1_PORT), format),
+    }
+}
@@ -0,0 +1,127 @@
+use spin::Mutex;
+
+use crate::{
+    arch, cmdline, console,
+    graphics::{self, Rect},
+    image::Image,
+    requests::MODULE_REQUEST,
+    screen::Color,
+};
+
+/// Passing this flag on the command line disables the boot splash
+pub const DISABLE_FLAG: &str = "nosplash";
+
+/// The string that a Limine module must have to replace the embedded splash image
+pub const MODULE_STRING: &[u8] = b"splash";
+
+const BACKGROUND: Color = Color::BLACK;
+const PROGRESS_COLOR: Color = Color::new(255, 170, 60);
+
+static SPLASH: Mutex<Option<Splash>> = Mutex::new(None);
+
+struct Splash {
+    progress_bar: Rect,
+    stage: usize,
+}
+
+impl Splash {
+    fn draw_progress(&self) {
+        let mut canvas = graphics::get_screen_canvas();
+
+        // Leave a one pixel gap between the outline and the filled part
+        let inner = Rect::new(
+            self.progress_bar.x + 2,
+            self.progress_bar.y + 2,
+            self.progress_bar.width - 4,
+            self.progress_bar.height - 4,
+        );
+
+        let filled_width =
+            inner.width * self.stage.min(arch::INIT_STAGE_COUNT) / arch::INIT_STAGE_COUNT;
+
+        canvas.fill_rect(
+            Rect::new(inner.x, inner.y, filled_width, inner.height),
+            PROGRESS_COLOR,
+        );
+    }
+}
+
+/// The size of `image` scaled to fit in `max_width` by `max_height` while keeping its aspect
+/// ratio, small images are only scaled up by whole factors so they stay sharp
+fn fit(image: &Image, max_width: usize, max_height: usize) -> (usize, usize) {
+    if image.width <= max_width && image.height <= max_height {
+        let factor = (max_width / image.width).min(max_height / image.height);
+
+        (image.width * factor, image.height * factor)
+    } else if image.width * max_height > image.height * max_width {
+        (max_width, image.height * max_width / image.width)
+    } else {
+        (image.width * max_height / image.height, max_height)
+    }
+}
+
+fn get_image_data() -> &'static [u8] {
+    MODULE_REQUEST
+        .get_response()
+        .and_then(|response| {
+            response
+                .modules()
+                .iter()
+                .find(|module| module.string().to_bytes() == MODULE_STRING)
+        })
+        .map(|module| unsafe { core::slice::from_raw_parts(module.addr(), module.size() as usize) })
+        .unwrap_or(include_bytes!("images/splash.qoi"))
+}
+
+/// Draws the splash image centered on the screen with an empty progress bar below it
+pub fn show() {
+    if cmdline::has_flag(DISABLE_FLAG) {
+        return;
+    }
+
+    let Ok(image) = Image::decode(get_image_data()) else {
+        return;
+    };
+
+    let mut canvas = graphics::get_screen_canvas();
+
+    let (width, height) = fit(&image, canvas.width() / 2, canvas.height() / 2);
+
+    let x = (canvas.width() - width) / 2;
+    let y = (canvas.height() - height) / 2;
+
+    canvas.clear(BACKGROUND);
+    canvas.blit_scaled(
+        Rect::new(x as isize, y as isize, width, height),
+        &image.as_bitmap(),
+    );
+
+    let progress_bar = Rect::new(
+        (canvas.width() / 3) as isize,
+        (y + height + canvas.height() / 16) as isize,
+        canvas.width() / 3,
+        12,
+    );
+
+    canvas.draw_rect(progress_bar, 1, PROGRESS_COLOR);
+
+    *SPLASH.lock() = Some(Splash {
+        progress_bar,
+        stage: 0,
+    });
+}
+
+/// Advances the progress bar by one stage, does nothing if the splash is not shown
+pub fn advance() {
+    if let Some(splash) = SPLASH.lock().as_mut() {
+        splash.stage += 1;
+        splash.draw_progress();
+    }
+}
+
+/// Removes the splash and gives the screen back to the active virtual terminal
+pub fn finish() {
+    if SPLASH.lock().take().is_some() {
+        console::get_terminal(console::get_active_terminal()).set_active(true);
+    }
+}